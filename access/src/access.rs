@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use serde::Deserialize;
+
+pub const FILE_NAME: &str = "access.toml";
+
+pub const SESSION_COOKIE: &str = "rrr-session";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Can view pages, and refresh what is shown, but cannot change any files.
+    /// So a refresh fetches the remote feeds, but does not save any of them
+    /// that moved or are gone.
+    ReadOnly,
+    ReadWrite,
+}
+
+impl Role {
+    pub fn can_write(self) -> bool {
+        self == Self::ReadWrite
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct User {
+    name: String,
+    /// A PHC string, as produced by `hash_password`.
+    password_hash: String,
+    role: Role,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Token {
+    token: String,
+    role: Role,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    users: Vec<User>,
+    tokens: Vec<Token>,
+    /// How long after logging in users have to log in again.
+    session_max_age_hours: u64,
+}
+
+impl Default for File {
+    fn default() -> Self {
+        Self {
+            users: Vec::new(),
+            tokens: Vec::new(),
+            session_max_age_hours: 7 * 24,
+        }
+    }
+}
+
+/// A session ID, as stored in the session cookie.
+pub type SessionId = String;
+
+#[derive(Clone)]
+pub struct Session {
+    pub role: Role,
+    /// Included in forms shown during this session, and expected back when they
+    /// are submitted.
+    pub csrf_token: String,
+    started_at: Instant,
+}
+
+/// The credentials that are allowed to access the server, and the sessions that
+/// have been started with them.
+pub struct Access {
+    users: Vec<User>,
+    tokens: Vec<Token>,
+    session_max_age: Duration,
+    sessions: Mutex<HashMap<SessionId, Session>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+    PasswordHash(PathBuf, String, argon2::password_hash::Error),
+    Random(getrandom::Error),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "Could not read {}: {e}", path.display()),
+            Self::Parse(path, e) => write!(f, "Invalid access file {}: {e}", path.display()),
+            Self::PasswordHash(path, name, e) => write!(
+                f,
+                "Invalid password hash for user \"{name}\" in {}: {e}",
+                path.display()
+            ),
+            Self::Random(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Loads the access file from the given directory. If there is no access file,
+/// then `None` is returned, and the server should not require any credentials.
+pub fn load(dir: impl AsRef<Path>) -> Result<Option<Access>, Error> {
+    let path = dir.as_ref().join(FILE_NAME);
+
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(None)
+        }
+        Err(e) => return Err(Error::Io(path, e)),
+    };
+
+    let file: File = toml::from_str(&source)
+        .map_err(|e| Error::Parse(path.clone(), e))?;
+
+    // Check these up front, so a typo shows up at startup, instead of as a login
+    // that mysteriously never works.
+    for user in &file.users {
+        PasswordHash::new(&user.password_hash)
+            .map_err(|e| Error::PasswordHash(path.clone(), user.name.clone(), e))?;
+    }
+
+    Ok(Some(Access {
+        users: file.users,
+        tokens: file.tokens,
+        session_max_age: Duration::from_secs(
+            file.session_max_age_hours.saturating_mul(60 * 60)
+        ),
+        sessions: Mutex::new(HashMap::new()),
+    }))
+}
+
+/// Produces a random string which is infeasible to guess.
+pub fn random_token() -> Result<String, Error> {
+    let mut bytes = [0u8; 16];
+    getrandom::getrandom(&mut bytes).map_err(Error::Random)?;
+
+    let token = u128::from_le_bytes(bytes);
+
+    Ok(format!("{token:032x}"))
+}
+
+/// Produces a string suitable for use as a `password_hash` in the access file.
+pub fn hash_password(password: &str) -> Result<String, Error> {
+    let mut salt_bytes = [0; 16];
+    getrandom::getrandom(&mut salt_bytes).map_err(Error::Random)?;
+
+    let salt = argon2::password_hash::SaltString::encode_b64(&salt_bytes)
+        .expect("16 bytes is a valid salt length");
+
+    Ok(
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("Default Argon2 params should work with a valid salt")
+            .to_string()
+    )
+}
+
+impl Access {
+    pub fn check_password(&self, name: &str, password: &str) -> Option<Role> {
+        let user = self.users.iter().find(|user| user.name == name)?;
+
+        // We checked these when loading, so this should not fail.
+        let hash = PasswordHash::new(&user.password_hash).ok()?;
+
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .ok()
+            .map(|()| user.role)
+    }
+
+    pub fn check_token(&self, token: &str) -> Option<Role> {
+        self.tokens.iter()
+            .find(|t| constant_time_eq(t.token.as_bytes(), token.as_bytes()))
+            .map(|t| t.role)
+    }
+
+    pub fn start_session(&self, role: Role) -> Result<SessionId, Error> {
+        let id = random_token()?;
+
+        let session = Session {
+            role,
+            csrf_token: random_token()?,
+            started_at: Instant::now(),
+        };
+
+        let mut sessions = self.lock_sessions();
+
+        // Otherwise sessions that are never used again would pile up.
+        sessions.retain(|_, session| !self.has_expired(session));
+
+        sessions.insert(id.clone(), session);
+
+        Ok(id)
+    }
+
+    /// The session with the given ID, unless it has ended, or expired.
+    pub fn session(&self, id: &str) -> Option<Session> {
+        let mut sessions = self.lock_sessions();
+
+        match sessions.get(id) {
+            Some(session) if self.has_expired(session) => {
+                sessions.remove(id);
+                None
+            }
+            session => session.cloned(),
+        }
+    }
+
+    /// How long a session lasts, which is also how long the session cookie
+    /// should last.
+    pub fn session_max_age(&self) -> Duration {
+        self.session_max_age
+    }
+
+    fn has_expired(&self, session: &Session) -> bool {
+        session.started_at.elapsed() >= self.session_max_age
+    }
+
+    pub fn end_session(&self, id: &str) {
+        self.lock_sessions().remove(id);
+    }
+
+    fn lock_sessions(&self) -> std::sync::MutexGuard<'_, HashMap<SessionId, Session>> {
+        // A panic while holding this lock cannot leave the map half-updated in a
+        // way that matters, so we can just keep going.
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Compares the bytes in a way that does not leak how many leading bytes were
+/// correct through the timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false
+    }
+
+    a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(session_max_age: Duration) -> Access {
+        Access {
+            users: Vec::new(),
+            tokens: Vec::new(),
+            session_max_age,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn sessions_last_until_they_end() {
+        let access = access(Duration::from_secs(60 * 60));
+
+        let id = access.start_session(Role::ReadOnly).unwrap();
+        assert_eq!(
+            access.session(&id).map(|session| session.role),
+            Some(Role::ReadOnly),
+        );
+
+        access.end_session(&id);
+        assert!(access.session(&id).is_none());
+    }
+
+    #[test]
+    fn expired_sessions_are_rejected_and_pruned() {
+        let access = access(Duration::ZERO);
+
+        let id = access.start_session(Role::ReadWrite).unwrap();
+        assert!(access.session(&id).is_none());
+
+        access.start_session(Role::ReadWrite).unwrap();
+        access.start_session(Role::ReadWrite).unwrap();
+        assert_eq!(access.lock_sessions().len(), 1);
+    }
+
+    #[test]
+    fn session_max_age_defaults_to_a_week() {
+        let file: File = toml::from_str("").unwrap();
+        assert_eq!(file.session_max_age_hours, 7 * 24);
+
+        let file: File = toml::from_str("session_max_age_hours = 2").unwrap();
+        assert_eq!(file.session_max_age_hours, 2);
+    }
+}
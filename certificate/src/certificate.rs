@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+
+/// The default file names, for when no paths are configured.
+pub const CERTIFICATE_FILE_NAME: &str = "tls-certificate.pem";
+pub const PRIVATE_KEY_FILE_NAME: &str = "tls-private-key.pem";
+
+/// A PEM encoded certificate and private key.
+pub struct Pair {
+    pub certificate: Vec<u8>,
+    pub private_key: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Generate(rcgen::Error),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "{}: {e}", path.display()),
+            Self::Generate(e) => write!(f, "Could not generate certificate: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub fn load(certificate_path: &Path, private_key_path: &Path) -> Result<Pair, Error> {
+    let read = |path: &Path| std::fs::read(path)
+        .map_err(|e| Error::Io(path.to_owned(), e));
+
+    Ok(Pair {
+        certificate: read(certificate_path)?,
+        private_key: read(private_key_path)?,
+    })
+}
+
+/// Loads the certificate and key from the given paths, first generating a
+/// self-signed certificate for the given names, (host names or IP addresses,) if
+/// neither file exists yet.
+pub fn load_or_generate_self_signed(
+    certificate_path: &Path,
+    private_key_path: &Path,
+    names: Vec<String>,
+) -> Result<Pair, Error> {
+    if certificate_path.exists() || private_key_path.exists() {
+        return load(certificate_path, private_key_path)
+    }
+
+    let rcgen::CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(names)
+            .map_err(Error::Generate)?;
+
+    let pair = Pair {
+        certificate: cert.pem().into_bytes(),
+        private_key: key_pair.serialize_pem().into_bytes(),
+    };
+
+    write_private(private_key_path, &pair.private_key)?;
+    std::fs::write(certificate_path, &pair.certificate)
+        .map_err(|e| Error::Io(certificate_path.to_owned(), e))?;
+
+    Ok(pair)
+}
+
+/// Writes a file that only the current user can read.
+fn write_private(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)
+        .and_then(|mut file| file.write_all(bytes))
+        .map_err(|e| Error::Io(path.to_owned(), e))
+}
@@ -0,0 +1,264 @@
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use timestamp::{TimeZone, TimeZoneError, UtcOffset};
+
+pub const FILE_NAME: &str = "config.toml";
+
+/// Everything that can be set in the config file. Any key that is left out gets
+/// the default value, but any key we don't know about is an error, so that typos
+/// don't get silently ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: Server,
+    pub fetch: Fetch,
+    pub refresh: Refresh,
+    pub time: Time,
+    pub display: Display,
+    pub backups: Backups,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Server {
+    /// Used when no address is passed on the command line.
+    pub address: Option<String>,
+    /// Used when the address does not include a port.
+    pub port: u16,
+    pub tls: Tls,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self {
+            address: None,
+            port: 8080,
+            tls: Tls::default(),
+        }
+    }
+}
+
+/// Relative paths are relative to the data directory. If neither path is set,
+/// then a self-signed certificate is generated in the data directory the first
+/// time it is needed.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tls {
+    pub enabled: bool,
+    pub certificate: Option<PathBuf>,
+    pub private_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fetch {
+    pub connect_timeout_seconds: u64,
+    pub read_timeout_seconds: u64,
+    pub user_agent: String,
+    /// How many more times to try a request that failed in a way that might not
+    /// happen again.
+    pub retries: u32,
+    /// Doubled after each retry.
+    pub retry_delay_seconds: u64,
+    /// Also the longest a server can ask us to wait, before we give up instead.
+    pub max_retry_delay_seconds: u64,
+    /// How many feeds to fetch at once.
+    pub max_concurrent: usize,
+    /// How many of those can be from the same host.
+    pub max_per_host: usize,
+    pub min_host_interval_milliseconds: u64,
+    /// How many refreshes to skip a feed for after it fails, is based on this.
+    /// It doubles with each failure in a row.
+    pub failing_feed_backoff_seconds: u64,
+    pub max_failing_feed_backoff_seconds: u64,
+    /// How long an `exec:` feed's command can run before it is killed.
+    pub exec_timeout_seconds: u64,
+}
+
+impl Default for Fetch {
+    fn default() -> Self {
+        Self {
+            connect_timeout_seconds: 10,
+            read_timeout_seconds: 30,
+            user_agent: concat!("rrr/", env!("CARGO_PKG_VERSION")).to_owned(),
+            retries: 2,
+            retry_delay_seconds: 1,
+            max_retry_delay_seconds: 30,
+            max_concurrent: 4,
+            max_per_host: 2,
+            min_host_interval_milliseconds: 500,
+            failing_feed_backoff_seconds: 10 * 60,
+            max_failing_feed_backoff_seconds: 24 * 60 * 60,
+            exec_timeout_seconds: 60,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Refresh {
+    /// Whether to fetch all the remote feeds before starting the server.
+    pub remote_on_startup: bool,
+    /// Whether the server reloads local feeds, and the list of remote feeds,
+    /// when something else changes them.
+    pub watch: bool,
+    /// How long to wait for changes to stop, before reloading, so that an editor
+    /// saving in several steps only causes one reload.
+    pub watch_debounce_milliseconds: u64,
+}
+
+impl Default for Refresh {
+    fn default() -> Self {
+        Self {
+            remote_on_startup: true,
+            watch: true,
+            watch_debounce_milliseconds: 250,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Time {
+    pub timezone: Timezone,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Timezone {
+    /// Whatever the OS says the local timezone is, at startup.
+    #[default]
+    Local,
+    Fixed(UtcOffset),
+    /// From the tz database, like "Europe/Berlin".
+    Named(TimeZone),
+}
+
+impl TryFrom<String> for Timezone {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        match s.as_str() {
+            "local" => Ok(Self::Local),
+            "UTC" | "utc" => Ok(Self::Fixed(UtcOffset::UTC)),
+            _ if s.starts_with(['+', '-']) || s == "Z" => UtcOffset::parse(&s)
+                .map(Self::Fixed)
+                .map_err(|e| format!("{e}, or \"local\", or \"UTC\"")),
+            _ => TimeZone::named(&s)
+                .map(Self::Named)
+                .map_err(|e| format!(
+                    "{e}. Expected a name like \"Europe/Berlin\", an offset like \
+                    \"+09:30\", or \"local\", or \"UTC\""
+                )),
+        }
+    }
+}
+
+impl Timezone {
+    /// An error here means the OS did not say what the local timezone is.
+    pub fn time_zone(&self) -> Result<TimeZone, TimeZoneError> {
+        match self {
+            Self::Local => TimeZone::local(),
+            Self::Fixed(offset) => Ok(TimeZone::fixed(*offset)),
+            Self::Named(zone) => Ok(zone.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Display {
+    pub theme: Theme,
+    /// Whether the sections of posts on the home page start out expanded.
+    pub expand_sections: bool,
+    /// Whether to include the script that adds keyboard shortcuts to the home
+    /// page.
+    pub keyboard_shortcuts: bool,
+    /// Whether the home page should offer to show posts that arrive after it
+    /// was loaded, without reloading the whole page.
+    pub live_updates: bool,
+    /// How times are shown. The full time is always available in a tooltip.
+    pub time_format: TimeFormat,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self {
+            theme: Theme::default(),
+            expand_sections: false,
+            keyboard_shortcuts: true,
+            live_updates: true,
+            time_format: TimeFormat::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Whichever of the others the browser says the user prefers.
+    #[default]
+    Auto,
+    Dark,
+    Light,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeFormat {
+    /// Like "3 hours ago".
+    #[default]
+    Relative,
+    /// Like "18 Oct 2026".
+    Short,
+    Full,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Backups {
+    /// How many previous versions of each file to keep, when a file in the data
+    /// directory is overwritten. Zero turns backups off.
+    pub keep: usize,
+}
+
+impl Default for Backups {
+    fn default() -> Self {
+        Self {
+            keep: 10,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "Could not read {}: {e}", path.display()),
+            Self::Parse(path, e) => write!(f, "Invalid config in {}: {e}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Loads the config file from the given directory. If there is no config file,
+/// then the defaults are used.
+pub fn load(dir: impl AsRef<Path>) -> Result<Config, Error> {
+    let path = dir.as_ref().join(FILE_NAME);
+
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Config::default())
+        }
+        Err(e) => return Err(Error::Io(path, e)),
+    };
+
+    toml::from_str(&source).map_err(|e| Error::Parse(path, e))
+}
@@ -0,0 +1,269 @@
+use std::path::{Path, PathBuf};
+use base64::Engine as _;
+use serde::Deserialize;
+use url::Url;
+
+pub const FILE_NAME: &str = "credentials.toml";
+
+/// How to prove to a server that we are allowed to read a feed. None of these
+/// implement `Debug` or `Display`, so that they cannot end up in a log, or on a
+/// page, by accident.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Auth {
+    Basic {
+        username: String,
+        password: String,
+    },
+    Bearer {
+        token: String,
+    },
+    Header {
+        name: String,
+        value: String,
+    },
+}
+
+impl Auth {
+    /// The name and value of the header to send.
+    pub fn header(&self) -> (&str, String) {
+        match self {
+            Self::Basic { username, password } => (
+                "Authorization",
+                format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD
+                        .encode(format!("{username}:{password}"))
+                ),
+            ),
+            Self::Bearer { token } => ("Authorization", format!("Bearer {token}")),
+            Self::Header { name, value } => (name, value.clone()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Feed {
+    /// Requests to this URL, or to anything under it on the same server, are sent
+    /// the credentials.
+    url: String,
+    auth: Auth,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    feeds: Vec<Feed>,
+}
+
+/// The credentials to send with requests for particular feeds.
+#[derive(Default)]
+pub struct Credentials {
+    feeds: Vec<(Url, Auth)>,
+}
+
+impl Credentials {
+    /// The credentials whose URL is the longest one that `url` is under, if any.
+    pub fn for_url(&self, url: &Url) -> Option<&Auth> {
+        self.feeds.iter()
+            .filter(|(prefix, _)| {
+                prefix.origin() == url.origin()
+                && is_under(url.path(), prefix.path())
+            })
+            .max_by_key(|(prefix, _)| prefix.path().len())
+            .map(|(_, auth)| auth)
+    }
+}
+
+/// Whether `path` is `prefix`, or inside it. So "/api/feed" is under "/api", but
+/// "/api-public/feed" is not.
+fn is_under(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || prefix.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(PathBuf, std::io::Error),
+    /// Only the position is kept, since the message could include part of a
+    /// credential.
+    Parse(PathBuf, Option<usize>),
+    /// The file can be read by users other than its owner.
+    Permissions(PathBuf),
+    Url(PathBuf, String, url::ParseError),
+    /// The header name or value cannot be sent. We only say which feed it was for,
+    /// since the value could be a credential.
+    BadHeader(PathBuf, String),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "Could not read {}: {e}", path.display()),
+            Self::Parse(path, Some(line)) => write!(
+                f,
+                "Invalid credentials file {}, on line {line}",
+                path.display()
+            ),
+            Self::Parse(path, None) => write!(
+                f,
+                "Invalid credentials file {}",
+                path.display()
+            ),
+            Self::Permissions(path) => write!(
+                f,
+                "{} can be read by other users. Run `chmod 600` on it.",
+                path.display()
+            ),
+            Self::Url(path, url, e) => write!(
+                f,
+                "Invalid URL \"{url}\" in {}: {e}",
+                path.display()
+            ),
+            Self::BadHeader(path, url) => write!(
+                f,
+                "Invalid header for \"{url}\" in {}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Loads the credentials file from the given directory. If there is no
+/// credentials file, then no credentials are sent.
+pub fn load(dir: impl AsRef<Path>) -> Result<Credentials, Error> {
+    let path = dir.as_ref().join(FILE_NAME);
+
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Credentials::default())
+        }
+        Err(e) => return Err(Error::Io(path, e)),
+    };
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let metadata = std::fs::metadata(&path)
+            .map_err(|e| Error::Io(path.clone(), e))?;
+
+        if metadata.permissions().mode() & 0o077 != 0 {
+            return Err(Error::Permissions(path))
+        }
+    }
+
+    let file: File = toml::from_str(&source)
+        .map_err(|e| {
+            let line = e.span()
+                .map(|span| source[..span.start].matches('\n').count() + 1);
+
+            Error::Parse(path.clone(), line)
+        })?;
+
+    let mut feeds = Vec::with_capacity(file.feeds.len());
+
+    for feed in file.feeds {
+        let url = Url::parse(&feed.url)
+            .map_err(|e| Error::Url(path.clone(), feed.url.clone(), e))?;
+
+        // Check these up front, so a typo shows up at startup, instead of as an
+        // error that could include the value, every time the feed is fetched.
+        let (name, value) = feed.auth.header();
+        if !is_header_name(name) || !is_header_value(&value) {
+            return Err(Error::BadHeader(path, feed.url))
+        }
+
+        feeds.push((url, feed.auth));
+    }
+
+    Ok(Credentials { feeds })
+}
+
+fn is_header_name(name: &str) -> bool {
+    !name.is_empty()
+    && name.bytes().all(|b| {
+        b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+    })
+}
+
+fn is_header_value(value: &str) -> bool {
+    value.bytes().all(|b| b == b'\t' || (b' '..=b'~').contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(prefixes: &[&str]) -> Credentials {
+        Credentials {
+            feeds: prefixes.iter()
+                .map(|prefix| (
+                    Url::parse(prefix).unwrap(),
+                    Auth::Bearer { token: prefix.to_string() },
+                ))
+                .collect(),
+        }
+    }
+
+    fn token_for(credentials: &Credentials, url: &str) -> Option<String> {
+        credentials.for_url(&Url::parse(url).unwrap())
+            .map(|auth| auth.header().1)
+    }
+
+    #[test]
+    fn for_url_respects_path_segments() {
+        let credentials = credentials(&["https://host/api"]);
+
+        for url in [
+            "https://host/api",
+            "https://host/api/",
+            "https://host/api/feed.xml",
+            "https://host/api?format=rss",
+        ] {
+            assert!(token_for(&credentials, url).is_some(), "{url}");
+        }
+
+        for url in [
+            "https://host/api-public/feed.xml",
+            "https://host/apis",
+            "https://host/",
+            "http://host/api",
+            "https://other/api",
+        ] {
+            assert_eq!(token_for(&credentials, url), None, "{url}");
+        }
+    }
+
+    #[test]
+    fn for_url_prefers_the_longest_prefix() {
+        let credentials = credentials(&[
+            "https://host/",
+            "https://host/private/",
+            "https://host/private/extra",
+        ]);
+
+        assert_eq!(
+            token_for(&credentials, "https://host/public/feed").as_deref(),
+            Some("Bearer https://host/"),
+        );
+        assert_eq!(
+            token_for(&credentials, "https://host/private/feed").as_deref(),
+            Some("Bearer https://host/private/"),
+        );
+        assert_eq!(
+            token_for(&credentials, "https://host/private/extra/feed").as_deref(),
+            Some("Bearer https://host/private/extra"),
+        );
+        assert_eq!(
+            token_for(&credentials, "https://host/private/extras").as_deref(),
+            Some("Bearer https://host/private/"),
+        );
+    }
+}
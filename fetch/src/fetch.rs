@@ -1,23 +1,506 @@
-pub use ureq::Error;
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
 pub use url::{
     Url,
     ParseError as UrlParseError,
 };
 
+/// How many redirects we will follow for one request, before giving up.
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Clone, Debug)]
+pub struct Redirect {
+    pub status: u16,
+    pub from: Url,
+    pub to: Url,
+}
+
+impl Redirect {
+    /// Whether the server says that `to` should be used instead of `from` from
+    /// now on.
+    pub fn is_permanent(&self) -> bool {
+        matches!(self.status, 301 | 308)
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    /// Any redirects that were followed before the error happened, in order.
+    pub redirects: Vec<Redirect>,
+    kind: ErrorKind,
+}
+
+#[derive(Debug)]
+enum ErrorKind {
+    /// `ureq::Error` is large enough that passing it around unboxed bloats every
+    /// `Result` that might contain it.
+    Request(Box<ureq::Error>),
+    /// An error status that did not come from `ureq`.
+    Status(u16),
+    /// From reading a `file:` URL.
+    Io(std::io::Error),
+    NotAFilePath,
+    TooManyRedirects,
+    BadRedirect(Option<String>),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.kind {
+            ErrorKind::Request(e) => write!(f, "{e}"),
+            ErrorKind::Status(status) => write!(f, "status code {status}"),
+            ErrorKind::Io(e) => write!(f, "{e}"),
+            ErrorKind::NotAFilePath => write!(f, "Not a path to a local file"),
+            ErrorKind::TooManyRedirects => write!(
+                f,
+                "Gave up after {MAX_REDIRECTS} redirects"
+            ),
+            ErrorKind::BadRedirect(Some(location)) => write!(
+                f,
+                "Could not follow redirect to {location:?}"
+            ),
+            ErrorKind::BadRedirect(None) => write!(
+                f,
+                "Got a redirect without a location"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// The status of the response that caused the error, if there was one.
+    pub fn status(&self) -> Option<u16> {
+        match &self.kind {
+            ErrorKind::Request(e) => match **e {
+                ureq::Error::Status(status, _) => Some(status),
+                ureq::Error::Transport(_) => None,
+            },
+            ErrorKind::Status(status) => Some(*status),
+            ErrorKind::Io(_)
+            | ErrorKind::NotAFilePath => None,
+            ErrorKind::TooManyRedirects
+            | ErrorKind::BadRedirect(_) => self.redirects.last()
+                .map(|redirect| redirect.status),
+        }
+    }
+
+    /// How long the server asked us to wait before trying again, if it did.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match &self.kind {
+            ErrorKind::Request(e) => retry_after(e),
+            ErrorKind::Status(_)
+            | ErrorKind::Io(_)
+            | ErrorKind::NotAFilePath
+            | ErrorKind::TooManyRedirects
+            | ErrorKind::BadRedirect(_) => None,
+        }
+    }
+}
+
+pub struct Settings<'user_agent> {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub user_agent: &'user_agent str,
+    /// How many more times to try a request that failed in a way that might not
+    /// happen again, like a dropped connection or a 503.
+    pub retries: u32,
+    /// How long to wait before the first retry. This doubles after each retry.
+    pub retry_delay: Duration,
+    /// The longest we will wait before a retry. If a server asks us to wait
+    /// longer than this, with a `Retry-After` header, we give up instead.
+    pub max_retry_delay: Duration,
+    /// How many requests to the same host can be in flight at once.
+    pub max_per_host: usize,
+    /// How long to wait between starting requests to the same host.
+    pub min_host_interval: Duration,
+    pub credentials: credentials::Credentials,
+}
+
+pub struct Response {
+    /// Any redirects that were followed to get here, in order.
+    pub redirects: Vec<Redirect>,
+    pub status: u16,
+    /// The value of the `Content-Type` header, if there was one.
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// The result of a single request.
+enum Hop {
+    Arrived {
+        status: u16,
+        content_type: Option<String>,
+        body: Vec<u8>,
+    },
+    Redirected {
+        status: u16,
+        location: Option<String>,
+    },
+}
+
+/// Something that can get the contents of a URL. `Agent` is the one that goes
+/// over the network; `Fixtures` is for when we don't want to do that.
+pub trait Fetcher: Send + Sync {
+    fn get(&self, url: &Url) -> Result<Response, Error>;
+}
+
+/// Calls `hop` for `url`, and then for wherever each redirect it returns points
+/// to, until it returns something else.
+fn follow_redirects(
+    url: &Url,
+    mut hop: impl FnMut(&Url) -> Result<Hop, ErrorKind>,
+) -> Result<Response, Error> {
+    let mut redirects = Vec::new();
+    let mut url = url.clone();
+
+    loop {
+        let hop = match hop(&url) {
+            Ok(hop) => hop,
+            Err(kind) => return Err(Error {
+                redirects,
+                kind,
+            }),
+        };
+
+        match hop {
+            Hop::Arrived { status, content_type, body } => {
+                return Ok(Response {
+                    redirects,
+                    status,
+                    content_type,
+                    body,
+                })
+            }
+            Hop::Redirected { status, location } => {
+                if redirects.len() >= MAX_REDIRECTS {
+                    return Err(Error {
+                        redirects,
+                        kind: ErrorKind::TooManyRedirects,
+                    })
+                }
+
+                // Anything else could have us read local files, or worse, and
+                // permanent redirects get saved as the feed's new URL.
+                let Some(to) = location.as_deref()
+                    .and_then(|location| url.join(location).ok())
+                    .filter(|to| matches!(to.scheme(), "http" | "https")) else {
+                    return Err(Error {
+                        redirects,
+                        kind: ErrorKind::BadRedirect(location),
+                    })
+                };
+
+                redirects.push(Redirect {
+                    status,
+                    from: url,
+                    to: to.clone(),
+                });
+
+                url = to;
+            }
+        }
+    }
+}
+
+/// Holds onto things like connection pools, so it should be reused across calls.
+pub struct Agent {
+    agent: ureq::Agent,
+    retries: u32,
+    retry_delay: Duration,
+    max_retry_delay: Duration,
+    hosts: HostLimits,
+    credentials: credentials::Credentials,
+}
+
+impl Agent {
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                // We follow these ourselves, so that we can report them.
+                .redirects(0)
+                .timeout_connect(settings.connect_timeout)
+                .timeout_read(settings.read_timeout)
+                .user_agent(settings.user_agent)
+                .build(),
+            retries: settings.retries,
+            retry_delay: settings.retry_delay,
+            max_retry_delay: settings.max_retry_delay,
+            hosts: HostLimits {
+                // Zero would mean never making any requests at all.
+                max_per_host: settings.max_per_host.max(1),
+                min_interval: settings.min_host_interval,
+                hosts: Mutex::new(HashMap::new()),
+                changed: Condvar::new(),
+            },
+            credentials: settings.credentials,
+        }
+    }
+
+    fn get_with_retries(&self, url: &Url) -> Result<Hop, Box<ureq::Error>> {
+        let mut delay = self.retry_delay;
+        let mut retries_left = self.retries;
+
+        loop {
+            let error = match self.get_once(url) {
+                Ok(hop) => return Ok(hop),
+                Err(e) => e,
+            };
+
+            if retries_left == 0 || !is_transient(&error) {
+                return Err(error)
+            }
+
+            let wait = match retry_after(&error) {
+                Some(requested) if requested > self.max_retry_delay => {
+                    return Err(error)
+                }
+                Some(requested) => requested,
+                None => delay.min(self.max_retry_delay),
+            };
+
+            std::thread::sleep(wait);
+
+            delay = delay.saturating_mul(2);
+            retries_left -= 1;
+        }
+    }
+
+    fn get_once(&self, url: &Url) -> Result<Hop, Box<ureq::Error>> {
+        use std::io::Read;
+
+        let _permit = self.hosts.acquire(url.host_str().unwrap_or_default());
+
+        let mut request = self.agent.request_url("GET", url);
+
+        // This is checked for each redirect separately, so that a feed that
+        // redirects somewhere else does not get sent the credentials.
+        if let Some(auth) = self.credentials.for_url(url) {
+            let (name, value) = auth.header();
+            request = request.set(name, &value);
+        }
+
+        let response = request
+            .call()
+            .map_err(Box::new)?;
 
-static AGENT: once_cell::sync::Lazy<ureq::Agent> = once_cell::sync::Lazy::new(|| {
-    ureq::AgentBuilder::new()
-        .build()
-});
-
-pub fn get(url: &Url) -> Result<
-    std::io::BufReader<impl std::io::Read + Send>,
-    Error
-> {
-    AGENT
-        .request_url("GET", url)
-        .call()
-        .map(|resp| {
-            std::io::BufReader::new(resp.into_reader())
+        let status = response.status();
+
+        if (300..400).contains(&status) {
+            return Ok(Hop::Redirected {
+                status,
+                location: response.header("Location").map(str::to_owned),
+            })
+        }
+
+        let content_type = response.header("Content-Type").map(str::to_owned);
+
+        let mut body = Vec::with_capacity(4096);
+        response.into_reader()
+            .read_to_end(&mut body)
+            .map_err(|e| Box::new(ureq::Error::from(e)))?;
+
+        Ok(Hop::Arrived {
+            status,
+            content_type,
+            body,
         })
+    }
+}
+
+impl Fetcher for Agent {
+    /// Reads the whole body of the response, following any redirects. This may
+    /// block for a while, to stay within the per host limits, and to wait between
+    /// retries. `file:` URLs are read from the local file system instead.
+    fn get(&self, url: &Url) -> Result<Response, Error> {
+        if url.scheme() == "file" {
+            return read_file(url).map_err(|kind| Error {
+                redirects: Vec::new(),
+                kind,
+            })
+        }
+
+        follow_redirects(
+            url,
+            |url| self.get_with_retries(url).map_err(ErrorKind::Request),
+        )
+    }
+}
+
+fn read_file(url: &Url) -> Result<Response, ErrorKind> {
+    let path = url.to_file_path()
+        .map_err(|()| ErrorKind::NotAFilePath)?;
+
+    let body = std::fs::read(path).map_err(ErrorKind::Io)?;
+
+    Ok(Response {
+        redirects: Vec::new(),
+        status: 200,
+        // Unlike with HTTP, there is nowhere for this to come from, so the body
+        // will need to say what encoding it uses, if it is not UTF-8.
+        content_type: None,
+        body,
+    })
+}
+
+/// What `Fixtures` responds with for a given URL.
+#[derive(Clone, Debug)]
+pub enum Fixture {
+    Body {
+        content_type: Option<String>,
+        body: Vec<u8>,
+    },
+    Redirect {
+        status: u16,
+        location: String,
+    },
+    /// An error status, like 404.
+    Status(u16),
+}
+
+/// A `Fetcher` that never touches the network, and instead responds with
+/// whatever it was given for each URL, or a 404 for anything else. This allows
+/// exercising things like refreshes on an offline machine.
+#[derive(Clone, Debug, Default)]
+pub struct Fixtures {
+    fixtures: HashMap<Url, Fixture>,
+}
+
+impl Fixtures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces any fixture previously inserted for `url`.
+    pub fn insert(&mut self, url: Url, fixture: Fixture) -> &mut Self {
+        self.fixtures.insert(url, fixture);
+        self
+    }
+}
+
+impl Fetcher for Fixtures {
+    fn get(&self, url: &Url) -> Result<Response, Error> {
+        follow_redirects(
+            url,
+            |url| match self.fixtures.get(url) {
+                Some(Fixture::Body { content_type, body }) => Ok(Hop::Arrived {
+                    status: 200,
+                    content_type: content_type.clone(),
+                    body: body.clone(),
+                }),
+                Some(Fixture::Redirect { status, location }) => Ok(Hop::Redirected {
+                    status: *status,
+                    location: Some(location.clone()),
+                }),
+                Some(Fixture::Status(status)) => Err(ErrorKind::Status(*status)),
+                None => Err(ErrorKind::Status(404)),
+            }
+        )
+    }
+}
+
+/// Whether trying the same request again might work.
+fn is_transient(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::Status(status, _) => matches!(
+            status,
+            408 | 429 | 500 | 502 | 503 | 504
+        ),
+        ureq::Error::Transport(transport) => matches!(
+            transport.kind(),
+            ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+        ),
+    }
+}
+
+fn retry_after(error: &ureq::Error) -> Option<Duration> {
+    let ureq::Error::Status(429 | 503, response) = error else {
+        return None
+    };
+
+    let value = response.header("Retry-After")?.trim();
+
+    // This can be either a number of seconds, or a date.
+    match value.parse() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => httpdate::parse_http_date(value).ok()
+            .map(|date| {
+                date.duration_since(SystemTime::now())
+                    .unwrap_or_default()
+            }),
+    }
+}
+
+/// Keeps us from hitting any one host too hard, when fetching from multiple
+/// threads.
+struct HostLimits {
+    max_per_host: usize,
+    min_interval: Duration,
+    hosts: Mutex<HashMap<String, Host>>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct Host {
+    in_flight: usize,
+    last_started: Option<Instant>,
+}
+
+impl HostLimits {
+    /// Blocks until a request to `host` is allowed to start.
+    fn acquire(&self, host: &str) -> HostPermit<'_> {
+        // Each host's counts are updated all at once, so a panic while holding
+        // this cannot leave them half-updated.
+        let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+
+        loop {
+            let entry = hosts.entry(host.to_owned()).or_default();
+
+            if entry.in_flight >= self.max_per_host {
+                hosts = self.changed.wait(hosts)
+                    .unwrap_or_else(|e| e.into_inner());
+                continue
+            }
+
+            let now = Instant::now();
+            let wait = entry.last_started
+                .map(|last| (last + self.min_interval).saturating_duration_since(now))
+                .unwrap_or_default();
+
+            if wait.is_zero() {
+                entry.in_flight += 1;
+                entry.last_started = Some(now);
+
+                return HostPermit {
+                    limits: self,
+                    host: host.to_owned(),
+                }
+            }
+
+            hosts = self.changed.wait_timeout(hosts, wait)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+struct HostPermit<'limits> {
+    limits: &'limits HostLimits,
+    host: String,
+}
+
+impl Drop for HostPermit<'_> {
+    fn drop(&mut self) {
+        let mut hosts = self.limits.hosts.lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        if let Some(entry) = hosts.get_mut(&self.host) {
+            entry.in_flight = entry.in_flight.saturating_sub(1);
+        }
+
+        self.limits.changed.notify_all();
+    }
 }
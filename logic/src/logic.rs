@@ -1,9 +1,13 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use fetch::Url;
-use timestamp::{Timestamp, UtcOffset};
+use timestamp::{TimeZone, Timestamp};
 
+pub use config::{self, Config};
+pub use render::{form_names, page_names};
 pub use syndicated::Post;
 
 macro_rules! other {
@@ -42,71 +46,632 @@ impl TryFrom<PathBuf> for Root {
     }
 }
 
+#[derive(Clone)]
 struct Posts {
     posts: Vec<syndicated::Post>,
     fetched_at: Timestamp,
 }
 
 enum FetchRemoteFeedsError {
-    Io(std::io::Error),
     Fetch(fetch::Error),
+    CommandIo(std::io::Error),
+    CommandFailed {
+        status: std::process::ExitStatus,
+        stderr: String,
+    },
+    CommandTimedOut(std::time::Duration),
 }
 
 impl core::fmt::Display for FetchRemoteFeedsError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
-            Self::Io(e) => write!(f, "{e}"),
             Self::Fetch(e) => write!(f, "{e}"),
+            Self::CommandIo(e) => write!(f, "Could not run command: {e}"),
+            Self::CommandFailed { status, stderr } => {
+                write!(f, "Command failed with {status}")?;
+
+                let stderr = stderr.trim_end();
+                if !stderr.is_empty() {
+                    write!(f, ":\n{stderr}")?;
+                }
+
+                Ok(())
+            }
+            Self::CommandTimedOut(timeout) => write!(
+                f,
+                "Command was killed after running for {} seconds",
+                timeout.as_secs()
+            ),
         }
     }
 }
 
-/// `output` will be cleared before being filled with the current posts.
-fn fetch_remote_feeds(
-    output: &mut RemotePosts,
-    utc_offset: UtcOffset,
-) -> Result<(), FetchRemoteFeedsError> {
-    let now = Timestamp::now_at_offset(utc_offset);
+/// The scheme for remote feeds that are the output of a shell command, rather
+/// than something to download. For example, `exec:git log`.
+const EXEC_SCHEME: &str = "exec";
 
-    for (feed, posts) in output.iter_mut() {
-        use std::io::Read;
+/// Whether `url` is something to download, as opposed to a command to run or
+/// a local file.
+fn is_web_url(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+}
 
-        posts.posts.clear();
-        posts.fetched_at = now;
+/// Appends the posts from the feed at `url` to `output`. Returns any redirects
+/// that were followed to get to the feed.
+///
+/// The remote feeds file is trusted input: an `exec:` URL in it is run as a
+/// shell command, and a `file:` URL or absolute path is read from disk. Only the
+/// owner of the data directory should be able to put lines there, so nothing
+/// that comes back from a server may add, move or rewrite such a line.
+fn fetch_remote_feed(
+    url: &Url,
+    state: &State,
+    output: &mut Vec<syndicated::Post>,
+) -> Result<Vec<fetch::Redirect>, FetchRemoteFeedsError> {
+    let (buffer, redirects) = if url.scheme() == EXEC_SCHEME {
+        let stdout = run_feed_command(
+            url,
+            std::time::Duration::from_secs(state.config.fetch.exec_timeout_seconds),
+            &state.root.0,
+        )?;
 
-        let mut reader = fetch::get(&feed.url)
+        (syndicated::decode(&stdout, None), Vec::new())
+    } else {
+        let response = state.fetcher.get(url)
             .map_err(FetchRemoteFeedsError::Fetch)?;
 
-        let mut buffer = String::with_capacity(4096);
-        reader.read_to_string(&mut buffer)
-            .map_err(FetchRemoteFeedsError::Io)?;
+        (
+            syndicated::decode(
+                &response.body,
+                response.content_type.as_deref(),
+            ),
+            response.redirects,
+        )
+    };
 
-        syndicated::parse_items(
-            std::io::Cursor::new(&buffer),
-            &mut posts.posts,
-        );
+    parse_items(
+        std::io::Cursor::new(&buffer),
+        output,
+        &state.time_zone,
+    );
+
+    Ok(redirects)
+}
+
+/// Like `syndicated::parse_items`, but with the dates in `time_zone`, so they
+/// are shown the same way as the times we produce ourselves.
+fn parse_items(
+    buf_read: impl std::io::BufRead + std::io::Seek,
+    output: &mut Vec<Post>,
+    time_zone: &TimeZone,
+) {
+    let start = output.len();
+
+    syndicated::parse_items(buf_read, output);
+
+    for post in &mut output[start..] {
+        dates_in_zone(post, time_zone);
     }
+}
 
-    Ok(())
+fn dates_in_zone(post: &mut Post, time_zone: &TimeZone) {
+    post.published = post.published.map(|at| at.in_zone(time_zone));
+    post.updated = post.updated.map(|at| at.in_zone(time_zone));
+}
+
+/// Runs the command in an `exec:` URL with the shell, in `dir`, and returns what
+/// it wrote to stdout.
+fn run_feed_command(
+    url: &Url,
+    timeout: std::time::Duration,
+    dir: &Path,
+) -> Result<Vec<u8>, FetchRemoteFeedsError> {
+    use std::process::{Command, Stdio};
+    use FetchRemoteFeedsError as E;
+
+    /// Both pipes need to be read while the command runs, or it could block
+    /// forever on writing to a full one.
+    fn read_in_background(
+        mut pipe: impl std::io::Read + Send + 'static
+    ) -> std::thread::JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let mut buffer = Vec::new();
+            // If this fails, we would rather have the partial output than none.
+            let _ = pipe.read_to_end(&mut buffer);
+            buffer
+        })
+    }
+
+    // Everything after the scheme, including anything that `Url` thinks is a
+    // query or fragment, is the command. `Url` percent-encodes some of it, so we
+    // undo that.
+    let command = percent_encoding::percent_decode_str(
+        &url.as_str()[EXEC_SCHEME.len() + 1..]
+    ).decode_utf8_lossy();
+
+    let mut shell = Command::new("sh");
+    shell.arg("-c")
+        .arg(&*command)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    // In a group of its own, so that anything the shell starts can be killed
+    // along with it.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+
+    let mut child = shell.spawn().map_err(E::CommandIo)?;
+
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+
+    let deadline = std::time::Instant::now() + timeout;
+
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(E::CommandIo)? {
+            break status
+        }
+
+        if std::time::Instant::now() >= deadline {
+            kill_group(&mut child);
+            let _ = child.wait();
+
+            // We don't wait for the readers here, since anything that left the
+            // group may still be holding the pipes open.
+            return Err(E::CommandTimedOut(timeout))
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    };
+
+    // The shell has exited, but anything it left running in the background may
+    // still be holding the pipes open, so we only wait for them until the
+    // deadline, then kill whatever is left.
+    while [&stdout, &stderr].iter().any(|reader| {
+        reader.as_ref().is_some_and(|reader| !reader.is_finished())
+    }) {
+        if std::time::Instant::now() >= deadline {
+            kill_group(&mut child);
+            break
+        }
+
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    // Killing the group closes the pipes, unless something left the group, in
+    // which case we give up on reading the rest.
+    let join = |reader: Option<std::thread::JoinHandle<Vec<u8>>>| {
+        reader.filter(|reader| {
+            for _ in 0..10 {
+                if reader.is_finished() {
+                    return true
+                }
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            false
+        })
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default()
+    };
+
+    let stdout = join(stdout);
+    let stderr = join(stderr);
+
+    if status.success() {
+        Ok(stdout)
+    } else {
+        Err(E::CommandFailed {
+            status,
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        })
+    }
+}
+
+/// Kills `child`, and everything else in the process group that
+/// `run_feed_command` put it in.
+fn kill_group(child: &mut std::process::Child) {
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: `kill` does not touch our memory. Since the child was made
+        // the leader of a new group, the group id is its pid, and that id is not
+        // reused while anything is left in the group.
+        unsafe {
+            libc::kill(-pid, libc::SIGKILL);
+        }
+    }
+
+    // Elsewhere, this is the best we can do.
+    let _ = child.kill();
+}
+
+/// The feeds that could not be fetched, and why.
+#[derive(Debug)]
+pub struct FailedFeeds(Vec<(Url, String)>);
+
+impl core::fmt::Display for FailedFeeds {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Could not fetch {} remote feed(s):", self.0.len())?;
+
+        for (url, error) in &self.0 {
+            write!(f, "\n{url}: {error}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A feed that failed the last time we tried it. We don't try again until
+/// `retry_at`, so that a feed that is down for a while does not slow down every
+/// refresh, or add to the load on a struggling server.
+struct FailingFeed {
+    failures: u32,
+    retry_at: std::time::Instant,
+}
+
+enum FeedRefreshStatus {
+    Pending,
+    InFlight,
+    Done,
+    /// Done, but the feed has permanently moved to this URL, which has replaced
+    /// the old one in the remote feeds file.
+    Moved(Url),
+    Failed(String),
+    /// Skipped because of a `FailingFeed`.
+    BackingOff { failures: u32 },
+    /// The server says the feed is gone for good, so it has been added to the
+    /// gone feeds file.
+    Gone,
+}
+
+/// A fetch of the remote feeds that is happening on another thread. The posts
+/// from each feed are published as soon as that feed is done.
+struct RefreshJob {
+    feeds: Mutex<Vec<(OrderedUrl, FeedRefreshStatus)>>,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    /// Whether feeds that moved or are gone get saved as such. If not, a moved
+    /// feed is shown with the posts from where it moved to, and a gone one fails.
+    saves: bool,
+}
+
+impl RefreshJob {
+    fn new(snapshot: &Snapshot, saves: bool) -> Self {
+        Self {
+            feeds: Mutex::new(
+                snapshot.remote_posts
+                    .keys()
+                    .filter(|o_url| !snapshot.gone_feeds.contains(&o_url.url))
+                    .map(|o_url| (o_url.clone(), FeedRefreshStatus::Pending))
+                    .collect()
+            ),
+            cancelled: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            saves,
+        }
+    }
+
+    fn lock_feeds(&self) -> MutexGuard<'_, Vec<(OrderedUrl, FeedRefreshStatus)>> {
+        // Each status is replaced whole, so a panic while holding this cannot
+        // leave things half-updated.
+        self.feeds.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_status(&self, index: usize, status: FeedRefreshStatus) {
+        if let Some((_, s)) = self.lock_feeds().get_mut(index) {
+            *s = status;
+        }
+    }
+
+    fn run(&self, state: &State) {
+        /// Marks the job finished even if we panic, so that later refreshes are
+        /// not blocked forever waiting on this one.
+        struct Finish<'job>(&'job AtomicBool);
+
+        impl Drop for Finish<'_> {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::Release);
+            }
+        }
+
+        let _finish = Finish(&self.finished);
+
+        let o_urls: Vec<OrderedUrl> = self.lock_feeds()
+            .iter()
+            .map(|(o_url, _)| o_url.clone())
+            .collect();
+
+        let next_index = std::sync::atomic::AtomicUsize::new(0);
+
+        // The fetcher keeps us from hitting any one host too hard, so we only
+        // need to limit the total here.
+        let workers = state.config.fetch.max_concurrent.clamp(1, o_urls.len().max(1));
+
+        std::thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    if self.cancelled.load(Ordering::Acquire) {
+                        break
+                    }
+
+                    let index = next_index.fetch_add(1, Ordering::AcqRel);
+                    let Some(o_url) = o_urls.get(index) else {
+                        break
+                    };
+
+                    let status = self.fetch(index, o_url, state);
+
+                    self.set_status(index, status);
+                });
+            }
+        });
+    }
+
+    fn fetch(
+        &self,
+        index: usize,
+        o_url: &OrderedUrl,
+        state: &State,
+    ) -> FeedRefreshStatus {
+        let now = std::time::Instant::now();
+
+        if let Some(failing) = state.lock_failing_feeds().get(&o_url.url) {
+            if now < failing.retry_at {
+                return FeedRefreshStatus::BackingOff {
+                    failures: failing.failures,
+                }
+            }
+        }
+
+        self.set_status(index, FeedRefreshStatus::InFlight);
+
+        let fetched_at = Timestamp::now_in(&state.time_zone);
+        let mut posts = Vec::new();
+
+        let result = fetch_remote_feed(&o_url.url, state, &mut posts);
+
+        // Commands and local files are never moved, whatever they return.
+        let redirects = match &result {
+            _ if !is_web_url(&o_url.url) || !self.saves => &[],
+            Ok(redirects) => redirects.as_slice(),
+            Err(FetchRemoteFeedsError::Fetch(e)) => &e.redirects,
+            Err(_) => &[],
+        };
+
+        // A temporary redirect means that the URL we were sent to might not work
+        // later, so we only go as far as the last permanent one before that.
+        let moved_to = redirects.iter()
+            .take_while(|redirect| redirect.is_permanent())
+            .last()
+            .map(|redirect| redirect.to.clone());
+
+        let url = match moved_to {
+            Some(to) => {
+                let moved = state.update(|snapshot| move_remote_feed(
+                    &mut snapshot.remote_posts,
+                    &o_url.url,
+                    &to,
+                    &state.root,
+                    &state.backups(),
+                ));
+
+                if let Err(e) = moved {
+                    let error = format!(
+                        "Moved to {to}, but that could not be saved: {e}"
+                    );
+
+                    state.broadcast(Event::FeedError {
+                        url: o_url.url.to_string(),
+                        error: error.clone(),
+                    });
+
+                    return FeedRefreshStatus::Failed(error)
+                }
+
+                state.lock_failing_feeds().remove(&o_url.url);
+
+                to
+            }
+            None => o_url.url.clone(),
+        };
+
+        match result {
+            Ok(_) => {
+                state.lock_failing_feeds().remove(&url);
+
+                state.update_infallibly(|snapshot| {
+                    // The feed may have been removed while we were fetching, in
+                    // which case we don't want to add it back.
+                    let existing = snapshot.remote_posts
+                        .iter_mut()
+                        .find(|(o_url, _)| o_url.url == url);
+
+                    if let Some((_, existing)) = existing {
+                        *existing = Posts { posts, fetched_at };
+                    }
+                });
+
+                if url == o_url.url {
+                    FeedRefreshStatus::Done
+                } else {
+                    FeedRefreshStatus::Moved(url)
+                }
+            }
+            Err(FetchRemoteFeedsError::Fetch(e))
+                if e.status() == Some(410) && is_web_url(&url) && self.saves =>
+            {
+                state.lock_failing_feeds().remove(&url);
+
+                let marked = state.update(|snapshot| mark_feed_gone(
+                    &mut snapshot.gone_feeds,
+                    &url,
+                    &state.root,
+                    &state.backups(),
+                ));
+
+                let (status, error) = match marked {
+                    Ok(()) => (
+                        FeedRefreshStatus::Gone,
+                        "The feed is gone, so it will not be fetched again".to_owned(),
+                    ),
+                    Err(e) => {
+                        let error = format!(
+                            "The feed is gone, but that could not be saved: {e}"
+                        );
+
+                        (FeedRefreshStatus::Failed(error.clone()), error)
+                    }
+                };
+
+                state.broadcast(Event::FeedError {
+                    url: url.to_string(),
+                    error,
+                });
+
+                status
+            }
+            Err(e) => {
+                let settings = &state.config.fetch;
+
+                let mut failing_feeds = state.lock_failing_feeds();
+                let failures = failing_feeds.get(&url)
+                    .map_or(0, |failing| failing.failures)
+                    .saturating_add(1);
+
+                let backoff = std::time::Duration::from_secs(
+                    settings.failing_feed_backoff_seconds
+                        .saturating_mul(1 << (failures - 1).min(32))
+                        .min(settings.max_failing_feed_backoff_seconds)
+                );
+
+                // If the server told us how long to wait, then we should wait at
+                // least that long.
+                let backoff = match &e {
+                    FetchRemoteFeedsError::Fetch(e) => e.retry_after()
+                        .map_or(backoff, |requested| requested.max(backoff)),
+                    FetchRemoteFeedsError::CommandIo(_)
+                    | FetchRemoteFeedsError::CommandFailed { .. }
+                    | FetchRemoteFeedsError::CommandTimedOut(_) => backoff,
+                };
+
+                failing_feeds.insert(
+                    url.clone(),
+                    FailingFeed {
+                        failures,
+                        retry_at: now + backoff,
+                    }
+                );
+                drop(failing_feeds);
+
+                let error = e.to_string();
+
+                state.broadcast(Event::FeedError {
+                    url: url.to_string(),
+                    error: error.clone(),
+                });
+
+                FeedRefreshStatus::Failed(error)
+            }
+        }
+    }
+
+    fn failed_feeds(&self) -> Result<(), FailedFeeds> {
+        let failed: Vec<(Url, String)> = self.lock_feeds()
+            .iter()
+            .filter_map(|(o_url, status)| match status {
+                FeedRefreshStatus::Failed(e) => Some((o_url.url.clone(), e.clone())),
+                _ => None,
+            })
+            .collect();
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(FailedFeeds(failed))
+        }
+    }
 }
 
 /// `output` will be cleared before being filled with the current paths and posts.
 fn load_local_posts(
     output: &mut LocalPosts,
     local_feeds_dir: &LocalFeedsDir,
-    utc_offset: UtcOffset,
+    time_zone: &TimeZone,
 ) -> std::io::Result<()> {
     load_local_feed_paths(output, local_feeds_dir)?;
 
     for (path, posts) in output.iter_mut() {
-        // TODO is it worth switching to reusing a single buffer across iterations?
-        let buffer = std::fs::read_to_string(path)?;
+        load_local_feed(posts, path.as_ref(), time_zone)?;
+    }
+
+    Ok(())
+}
+
+/// `output` will be cleared before being filled with the current posts. A local
+/// feed is either a feed file, or a directory of Markdown files.
+fn load_local_feed(
+    output: &mut Posts,
+    path: &Path,
+    time_zone: &TimeZone,
+) -> std::io::Result<()> {
+    if path.is_dir() {
+        return load_markdown_feed(output, path, time_zone)
+    }
+
+    // TODO is it worth switching to reusing a single buffer across feeds?
+    let buffer = read_local_feed(path)?;
+
+    load_local_post_from_buffer(
+        output,
+        std::io::Cursor::new(&buffer),
+        time_zone,
+    );
 
-        load_local_post_from_buffer(
-            posts,
-            std::io::Cursor::new(&buffer),
-            utc_offset,
+    Ok(())
+}
+
+/// Each Markdown file directly in `dir` is one post. See
+/// `syndicated::parse_markdown_post` for what can go in them.
+fn load_markdown_feed(
+    output: &mut Posts,
+    dir: &Path,
+    time_zone: &TimeZone,
+) -> std::io::Result<()> {
+    let mut paths = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        let is_markdown = path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("md")
+                || extension.eq_ignore_ascii_case("markdown")
+            });
+
+        if is_markdown && path.is_file() {
+            paths.push(path);
+        }
+    }
+
+    // So that undated posts keep the same order across reloads.
+    paths.sort();
+
+    output.posts.clear();
+    output.fetched_at = Timestamp::now_in(time_zone);
+
+    for path in paths {
+        let source = std::fs::read(&path)?;
+
+        let mut post = syndicated::parse_markdown_post(
+            &path.file_name().unwrap_or_default().to_string_lossy(),
+            &String::from_utf8_lossy(&source),
         );
+
+        dates_in_zone(&mut post, time_zone);
+
+        output.posts.push(post);
     }
 
     Ok(())
@@ -116,37 +681,81 @@ fn load_local_posts(
 fn load_local_post_from_buffer(
     output: &mut Posts,
     feed_buffer: impl std::io::BufRead + std::io::Seek,
-    utc_offset: UtcOffset,
+    time_zone: &TimeZone,
 ) {
     output.posts.clear();
-    output.fetched_at = Timestamp::now_at_offset(utc_offset);
+    output.fetched_at = Timestamp::now_in(time_zone);
 
-    syndicated::parse_items(
+    parse_items(
         feed_buffer,
         &mut output.posts,
+        time_zone,
     );
 }
 
-/// Any existing lists of posts will be left alone.
+/// Local feeds can be in any encoding that a remote feed could be, so we need
+/// to decode them.
+fn read_local_feed(path: impl AsRef<Path>) -> std::io::Result<String> {
+    std::fs::read(path).map(|bytes| syndicated::decode(&bytes, None))
+}
+
+/// `output` will be cleared, and then get an empty list of posts for each local
+/// feed, ready to be loaded.
 fn load_local_feed_paths(
     output: &mut LocalPosts,
     local_feeds_dir: &LocalFeedsDir,
 ) -> std::io::Result<()> {
     output.clear();
 
+    for path in local_feed_paths(local_feeds_dir)? {
+        output.insert(path, Posts {
+            posts: Vec::with_capacity(1024),
+            fetched_at: Timestamp::DEFAULT,
+        });
+    }
+
+    Ok(())
+}
+
+/// The local feeds that are in `local_feeds_dir` now, which can include ones
+/// that were added or removed since the posts were last loaded.
+fn local_feed_paths(
+    local_feeds_dir: &LocalFeedsDir,
+) -> std::io::Result<BTreeSet<LocalFeedPath>> {
+    let mut paths = BTreeSet::new();
+
     for entry in std::fs::read_dir(local_feeds_dir)? {
         let path = LocalFeedPath::new(entry?.path(), local_feeds_dir)
             .map_err(|BadPrefixError()|
                 other!("Got file that was not in the local_feeds_dir")
             )?;
 
-        output.entry(path)
-            .or_insert_with(|| Posts{
-                posts: Vec::with_capacity(1024),
-                fetched_at: Timestamp::DEFAULT,
-            });
+        paths.insert(path);
+    }
+
+    Ok(paths)
+}
+
+/// Loads the local feed at `path`, if it is one that was added since the posts
+/// were last loaded. The posts of the feeds that were already loaded are left
+/// alone.
+fn load_new_local_feed(
+    local_posts: &mut LocalPosts,
+    path: &LocalFeedPath,
+    time_zone: &TimeZone,
+) -> std::io::Result<()> {
+    if local_posts.contains_key(path) || !path.as_ref().exists() {
+        return Ok(())
     }
 
+    let mut posts = Posts {
+        posts: Vec::with_capacity(1024),
+        fetched_at: Timestamp::DEFAULT,
+    };
+    load_local_feed(&mut posts, path.as_ref(), time_zone)?;
+
+    local_posts.insert(path.clone(), posts);
+
     Ok(())
 }
 
@@ -171,7 +780,7 @@ mod local_feed_path {
 
     /// We keep the PathBuf field private so that one of these cannot be constructed
     /// without confirming the path is inside a `LocalFeedsDir`.
-    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
     #[repr(transparent)]
     pub struct LocalFeedPath(PathBuf);
 
@@ -216,7 +825,7 @@ type RemotePosts = BTreeMap<OrderedUrl, Posts>;
 // 64k order keys ought to be enough for anybody!
 type OrderKey = u16;
 
-#[derive(PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 struct OrderedUrl {
     /// This goes first so the derived `Ord` impl sorts by this, looking at the rest
     /// of the struct only to break ties.
@@ -224,48 +833,305 @@ struct OrderedUrl {
     url: Url,
 }
 
-pub struct State {
-    root: Root,
+/// The built-in styles followed by the user's styles, if any.
+#[derive(Clone)]
+struct Stylesheet {
+    css: String,
+    version: u64,
+}
+
+impl Stylesheet {
+    fn load(root: &Root) -> std::io::Result<Self> {
+        let mut css = render::BUILT_IN_STYLES.to_owned();
+
+        match std::fs::read_to_string(root.path_to(USER_STYLESHEET)) {
+            Ok(user_css) => {
+                css.push('\n');
+                css.push_str(&user_css);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        let version = stable_hash(css.as_bytes());
+
+        Ok(Self { css, version })
+    }
+}
+
+/// A hash that does not change between runs, or between versions of Rust.
+/// Currently FNV-1a.
+fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Everything we show that can change while we are running. Pages are rendered
+/// from one of these, and changes are made by building a new one and swapping it
+/// in, so a slow refresh does not hold up rendering.
+#[derive(Clone)]
+struct Snapshot {
+    stylesheet: Stylesheet,
     remote_posts: RemotePosts,
+    /// Remote feeds that are not fetched or shown, since the server said they
+    /// are gone.
+    gone_feeds: BTreeSet<Url>,
     local_posts: LocalPosts,
+}
+
+impl Snapshot {
+    fn post_ids(&self, root: &Root) -> std::collections::HashSet<u64> {
+        use render::{Data as _, PostHolder as _};
+
+        Data {
+            root,
+            local_posts: &self.local_posts,
+            remote_posts: &self.remote_posts,
+            gone_feeds: &self.gone_feeds,
+            // Only used for showing times.
+            now: Timestamp::DEFAULT,
+        }.post_sections()
+            .flat_map(|section| section.posts)
+            .map(|holder| holder.id().0)
+            .collect()
+    }
+}
+
+/// Something that happened in the background, which open pages may want to
+/// know about.
+#[derive(Clone)]
+enum Event {
+    /// The ids of posts that were not there before.
+    NewPosts(Vec<String>),
+    FeedError {
+        url: String,
+        error: String,
+    },
+}
+
+/// A subscription to the things that happen in the background, to be sent to a
+/// client as server-sent events.
+pub struct Events(mpsc::Receiver<Event>);
+
+impl Events {
+    /// Waits for the next event, and returns it in the `text/event-stream`
+    /// format. If nothing happens for a while, this returns a comment instead,
+    /// so that a client that went away is noticed without waiting for an event.
+    /// Returns `None` once no more events can happen.
+    pub fn next_message(&self) -> Option<String> {
+        use core::fmt::Write as _;
+
+        const KEEP_ALIVE_INTERVAL: std::time::Duration =
+            std::time::Duration::from_secs(15);
+
+        let mut message = String::new();
+
+        // Writing to a `String` cannot fail.
+        match self.0.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            Ok(Event::NewPosts(ids)) => {
+                let _ = writeln!(message, "event: posts\ndata: {}\n", ids.join(" "));
+            }
+            Ok(Event::FeedError { url, error }) => {
+                message.push_str("event: feed-error\n");
+
+                // Each line needs its own field, or it would end the event.
+                for line in format!("{url}: {error}").lines() {
+                    let _ = writeln!(message, "data: {line}");
+                }
+
+                message.push('\n');
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                message.push_str(": keep-alive\n\n");
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+        }
+
+        Some(message)
+    }
+}
+
+pub struct State {
+    root: Root,
+    config: Config,
+    fetcher: Box<dyn fetch::Fetcher>,
     local_feeds_dir: LocalFeedsDir,
-    utc_offset: UtcOffset,
+    time_zone: TimeZone,
+    snapshot: RwLock<Arc<Snapshot>>,
+    /// Held while building a new snapshot, so that two changes made at the same
+    /// time cannot both start from the same snapshot, and lose one of them.
+    publishing: Mutex<()>,
+    /// The most recently started background refresh, if any.
+    refresh_job: Mutex<Option<Arc<RefreshJob>>>,
+    subscribers: Mutex<Vec<mpsc::Sender<Event>>>,
+    failing_feeds: Mutex<HashMap<Url, FailingFeed>>,
+    /// Whether users log in, so pages should offer to log them out.
+    logins: bool,
 }
 
 impl State {
     pub fn root_display(&self) -> impl core::fmt::Display + '_ {
         self.root.display()
     }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn snapshot(&self) -> Arc<Snapshot> {
+        // Snapshots are only ever replaced whole, so a panic on another thread
+        // cannot have left this one half-changed.
+        Arc::clone(&self.snapshot.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Calls `f` on a copy of the current snapshot, then, if `f` succeeded, makes
+    /// the copy current. This may wait on other updates, but not on rendering, so
+    /// `f` should not do anything slow, like network requests.
+    fn update<A, E>(
+        &self,
+        f: impl FnOnce(&mut Snapshot) -> Result<A, E>
+    ) -> Result<A, E> {
+        let _publishing = self.publishing.lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        let previous = self.snapshot();
+        let mut snapshot = Snapshot::clone(&previous);
+
+        // If `f` failed, it may have left the copy half-changed, so we throw it
+        // away, and nobody hears about it.
+        let output = f(&mut snapshot)?;
+
+        let snapshot = Arc::new(snapshot);
+        *self.snapshot.write().unwrap_or_else(|e| e.into_inner()) = Arc::clone(&snapshot);
+
+        // Finding the new posts means looking at all of them, so we avoid it when
+        // nobody is listening.
+        if !self.lock_subscribers().is_empty() {
+            let previous_ids = previous.post_ids(&self.root);
+
+            let new_ids: Vec<String> = snapshot.post_ids(&self.root)
+                .difference(&previous_ids)
+                .map(|&id| PostId(id).to_string())
+                .collect();
+
+            if !new_ids.is_empty() {
+                self.broadcast(Event::NewPosts(new_ids));
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Like `update`, for changes that cannot fail.
+    fn update_infallibly(&self, f: impl FnOnce(&mut Snapshot)) {
+        let Ok(()) = self.update(|snapshot| {
+            f(snapshot);
+            Ok::<_, core::convert::Infallible>(())
+        });
+    }
+
+    fn lock_subscribers(&self) -> MutexGuard<'_, Vec<mpsc::Sender<Event>>> {
+        // A panic while holding this cannot leave the list half-updated in a way
+        // that matters.
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn subscribe(&self) -> Events {
+        let (sender, receiver) = mpsc::channel();
+
+        self.lock_subscribers().push(sender);
+
+        Events(receiver)
+    }
+
+    /// Also forgets about any subscribers that have gone away.
+    fn broadcast(&self, event: Event) {
+        self.lock_subscribers()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+
+    fn preferences(&self, snapshot: &Snapshot) -> render::Preferences {
+        let display = &self.config.display;
+
+        render::Preferences {
+            theme: match display.theme {
+                config::Theme::Auto => render::Theme::Auto,
+                config::Theme::Dark => render::Theme::Dark,
+                config::Theme::Light => render::Theme::Light,
+            },
+            expand_sections: display.expand_sections,
+            stylesheet_version: snapshot.stylesheet.version,
+            keyboard_shortcuts: display.keyboard_shortcuts,
+            keyboard_script_version: stable_hash(
+                render::KEYBOARD_SHORTCUTS_SCRIPT.as_bytes()
+            ),
+            live_updates: display.live_updates,
+            live_updates_script_version: stable_hash(
+                render::LIVE_UPDATES_SCRIPT.as_bytes()
+            ),
+            time_format: match display.time_format {
+                config::TimeFormat::Relative => render::TimeFormat::Relative,
+                config::TimeFormat::Short => render::TimeFormat::ShortDate,
+                config::TimeFormat::Full => render::TimeFormat::Full,
+            },
+            logins: self.logins,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum StateCreationError {
     RootMustBeDir,
+    Config(config::Error),
+    Credentials(credentials::Error),
+    TimeZone(timestamp::TimeZoneError),
     Io(std::io::Error),
     UrlParse(fetch::UrlParseError),
-    Fetch(fetch::Error),
+    FailedFeeds(FailedFeeds),
 }
 
 impl core::fmt::Display for StateCreationError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::RootMustBeDir => write!(f, "Root dir must be a dir"),
+            Self::Config(e) => write!(f, "{e}"),
+            Self::Credentials(e) => write!(f, "{e}"),
+            Self::TimeZone(e) => write!(
+                f,
+                "{e}. Set `timezone` in the `[time]` section of {} to choose one.",
+                config::FILE_NAME
+            ),
             Self::Io(e) => write!(f, "{e}"),
             Self::UrlParse(e) => write!(f, "{e}"),
-            Self::Fetch(e) => write!(f, "{e}"),
+            Self::FailedFeeds(e) => write!(f, "{e}"),
         }
     }
 }
 
 impl std::error::Error for StateCreationError {}
 
-impl From<FetchRemoteFeedsError> for StateCreationError {
-    fn from(e: FetchRemoteFeedsError) -> Self {
-        use FetchRemoteFeedsError as E;
-        match e {
-            E::Io(e) => Self::Io(e),
-            E::Fetch(e) => Self::Fetch(e),
-        }
+impl From<FailedFeeds> for StateCreationError {
+    fn from(e: FailedFeeds) -> Self {
+        Self::FailedFeeds(e)
+    }
+}
+
+impl From<config::Error> for StateCreationError {
+    fn from(e: config::Error) -> Self {
+        Self::Config(e)
+    }
+}
+
+impl From<credentials::Error> for StateCreationError {
+    fn from(e: credentials::Error) -> Self {
+        Self::Credentials(e)
+    }
+}
+
+impl From<timestamp::TimeZoneError> for StateCreationError {
+    fn from(e: timestamp::TimeZoneError) -> Self {
+        Self::TimeZone(e)
     }
 }
 
@@ -287,57 +1153,318 @@ impl From<RemoteFeedUrlsError> for StateCreationError {
 
 const LOCAL_FEEDS: &str = "local-feeds";
 const REMOTE_FEEDS: &str = "remote-feeds";
+const GONE_FEEDS: &str = "gone-feeds";
+const LOCK: &str = "lock";
+const BACKUPS: &str = "backups";
+const USER_STYLESHEET: &str = "style.css";
 
 impl TryFrom<PathBuf> for State {
     type Error = StateCreationError;
 
     fn try_from(root: PathBuf) -> Result<Self, Self::Error> {
+        let state = Self::open(root)?;
+
+        if state.config.refresh.remote_on_startup {
+            state.fetch_remote(true)?;
+        }
+
+        Ok(state)
+    }
+}
+
+impl State {
+    /// Like the `TryFrom<PathBuf>` impl, except that the remote feeds are not
+    /// fetched. This is useful when we only want to look at or change the data
+    /// directory, without waiting on the network.
+    pub fn open(root: PathBuf) -> Result<Self, StateCreationError> {
         let root = ensure_directory(root)?;
 
-        let root = Root::try_from(root)
-            .map_err(|MustBeDirError()| Self::Error::RootMustBeDir)?;
+        let root = Root::try_from(root)
+            .map_err(|MustBeDirError()| StateCreationError::RootMustBeDir)?;
+
+        let config = config::load(&root.0)?;
+
+        let time_zone = config.time.timezone.time_zone()?;
+
+        let agent = fetch::Agent::new(fetch::Settings {
+            connect_timeout: std::time::Duration::from_secs(
+                config.fetch.connect_timeout_seconds
+            ),
+            read_timeout: std::time::Duration::from_secs(
+                config.fetch.read_timeout_seconds
+            ),
+            user_agent: &config.fetch.user_agent,
+            retries: config.fetch.retries,
+            retry_delay: std::time::Duration::from_secs(
+                config.fetch.retry_delay_seconds
+            ),
+            max_retry_delay: std::time::Duration::from_secs(
+                config.fetch.max_retry_delay_seconds
+            ),
+            max_per_host: config.fetch.max_per_host,
+            min_host_interval: std::time::Duration::from_millis(
+                config.fetch.min_host_interval_milliseconds
+            ),
+            credentials: credentials::load(&root.0)?,
+        });
+
+        let stylesheet = Stylesheet::load(&root)?;
+
+        let mut local_posts = LocalPosts::new();
+
+        let local_feeds_dir = LocalFeedsDir::new(
+            &root,
+        )?;
+
+        load_local_posts(
+            &mut local_posts,
+            &local_feeds_dir,
+            &time_zone,
+        )?;
+
+        let mut remote_feeds_file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(root.path_to(REMOTE_FEEDS))?;
+
+        let mut remote_posts = RemotePosts::new();
+
+        load_remote_feed_urls(
+            &mut remote_feeds_file,
+            &mut remote_posts,
+        )?;
+
+        let gone_feeds = load_gone_feeds(&root)?;
+
+        Ok(Self {
+            root,
+            config,
+            fetcher: Box::new(agent),
+            local_feeds_dir,
+            time_zone,
+            snapshot: RwLock::new(Arc::new(Snapshot {
+                stylesheet,
+                remote_posts,
+                gone_feeds,
+                local_posts,
+            })),
+            publishing: Mutex::new(()),
+            refresh_job: Mutex::new(None),
+            subscribers: Mutex::new(Vec::new()),
+            failing_feeds: Mutex::new(HashMap::new()),
+            logins: false,
+        })
+    }
+
+    /// Replaces the fetcher made from the config, which goes over the network.
+    /// For example, with `fetch::Fixtures`, to refresh without a network.
+    pub fn with_fetcher(mut self, fetcher: impl fetch::Fetcher + 'static) -> Self {
+        self.fetcher = Box::new(fetcher);
+        self
+    }
+
+    /// For when users have to log in, so that pages include a way to log out.
+    pub fn with_logins(mut self) -> Self {
+        self.logins = true;
+        self
+    }
+
+    /// Takes the advisory lock on the data directory, which is released when the
+    /// returned value is dropped. This is for a server to hold while it runs, so
+    /// that two of them cannot overwrite each other's changes. Headless commands
+    /// don't take it, so that they still work while a server is running. Their
+    /// changes are protected by checking that a file has not changed since it
+    /// was read, before writing it.
+    pub fn lock_data_dir(&self) -> Result<DataDirLock, LockError> {
+        let path = self.root.path_to(LOCK);
+
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| LockError::Io(path, e))?;
+
+        match file.try_lock() {
+            Ok(()) => Ok(DataDirLock(file)),
+            Err(std::fs::TryLockError::WouldBlock) => {
+                Err(LockError::Locked(self.root.0.clone()))
+            },
+            Err(std::fs::TryLockError::Error(e)) => {
+                Err(LockError::Io(self.root.path_to(LOCK), e))
+            },
+        }
+    }
+}
+
+/// Holds the lock on the data directory until dropped.
+pub struct DataDirLock(#[allow(dead_code)] File);
+
+#[derive(Debug)]
+pub enum LockError {
+    Io(PathBuf, std::io::Error),
+    /// Another process holds the lock on this data directory.
+    Locked(PathBuf),
+}
+
+impl core::fmt::Display for LockError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "Could not lock {}: {e}", path.display()),
+            Self::Locked(root) => write!(
+                f,
+                "Another rrr server is already using {}",
+                root.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+/// Keeps the data directory watched until dropped.
+pub struct DataDirWatcher(#[allow(dead_code)] notify::RecommendedWatcher);
+
+#[derive(Debug)]
+pub struct WatchError(notify::Error);
+
+impl core::fmt::Display for WatchError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Could not watch the data directory: {}", self.0)
+    }
+}
+
+impl std::error::Error for WatchError {}
+
+impl From<notify::Error> for WatchError {
+    fn from(e: notify::Error) -> Self {
+        Self(e)
+    }
+}
+
+impl State {
+    /// Starts reloading local feeds, and the list of remote feeds, soon after
+    /// something else changes them, until the returned value is dropped. Only
+    /// the file that changed is reloaded. Changes are handled once they have
+    /// stopped for the configured debounce time, so a burst of them is handled
+    /// once.
+    pub fn watch_data_dir(self: &Arc<Self>) -> Result<DataDirWatcher, WatchError> {
+        use notify::Watcher as _;
+
+        let (sender, receiver) = mpsc::channel();
+
+        let mut watcher = notify::recommended_watcher(sender)?;
+
+        // The directories are watched, rather than the files, since writing a
+        // file atomically replaces it with a new one. The local feeds are watched
+        // recursively, for the Markdown feeds.
+        watcher.watch(&self.root.0, notify::RecursiveMode::NonRecursive)?;
+        watcher.watch(
+            self.local_feeds_dir.as_ref(),
+            notify::RecursiveMode::Recursive
+        )?;
+
+        let state = Arc::clone(self);
+        let debounce = std::time::Duration::from_millis(
+            self.config.refresh.watch_debounce_milliseconds
+        );
 
-        let utc_offset = UtcOffset::current_local_or_utc();
+        // This stops when the watcher is dropped, since that drops the sender.
+        std::thread::spawn(move || {
+            use std::sync::mpsc::RecvTimeoutError;
+
+            while let Ok(event) = receiver.recv() {
+                let mut changed = BTreeSet::new();
+
+                let mut add = |event: notify::Result<notify::Event>| match event {
+                    // We read these files ourselves when reloading them, so
+                    // reloading on reads would never stop.
+                    Ok(event) if event.kind.is_access() => {}
+                    Ok(event) => changed.extend(event.paths),
+                    Err(e) => eprintln!("{}", WatchError(e)),
+                };
+
+                add(event);
+
+                // Each change restarts the wait.
+                loop {
+                    match receiver.recv_timeout(debounce) {
+                        Ok(event) => add(event),
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
 
-        let mut local_posts = LocalPosts::new();
+                for path in changed {
+                    if let Err(e) = state.reload_changed(&path) {
+                        eprintln!("Could not reload {}: {e}", path.display());
+                    }
+                }
+            }
+        });
 
-        let local_feeds_dir = LocalFeedsDir::new(
-            &root,
-        )?;
+        Ok(DataDirWatcher(watcher))
+    }
 
-        load_local_posts(
-            &mut local_posts,
-            &local_feeds_dir,
-            utc_offset
-        )?;
+    /// Anything other than the remote feeds file, or a local feed, is ignored.
+    fn reload_changed(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if path == self.root.path_to(REMOTE_FEEDS) {
+            return self.update(|snapshot| reload_remote_feed_urls(
+                &mut snapshot.remote_posts,
+                &self.root,
+            )).map_err(Box::from)
+        }
 
-        let mut remote_feeds_file = std::fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(root.path_to(REMOTE_FEEDS))?;
+        // A change inside a Markdown feed's directory means reloading that feed.
+        let Some(name) = path.strip_prefix(&self.local_feeds_dir)
+            .ok()
+            .and_then(|relative| relative.components().next()) else {
+            return Ok(())
+        };
+
+        let feed = self.local_feeds_dir.as_ref().join(name);
+
+        let Ok(local_feed_path) = LocalFeedPath::new(
+            feed.clone(),
+            &self.local_feeds_dir,
+        ) else {
+            return Ok(())
+        };
+
+        if !feed.exists() {
+            self.update_infallibly(|snapshot| {
+                snapshot.local_posts.remove(&local_feed_path);
+            });
 
-        let mut remote_posts = RemotePosts::new();
+            return Ok(())
+        }
 
-        load_remote_feed_urls(
-            &mut remote_feeds_file,
-            &mut remote_posts,
-        )?;
+        let mut posts = Posts {
+            posts: Vec::new(),
+            fetched_at: Timestamp::DEFAULT,
+        };
 
-        fetch_remote_feeds(&mut remote_posts, utc_offset)?;
+        load_local_feed(&mut posts, &feed, &self.time_zone)?;
 
-        Ok(Self {
-            root,
-            remote_posts,
-            local_posts,
-            local_feeds_dir,
-            utc_offset,
-        })
+        self.update_infallibly(|snapshot| {
+            snapshot.local_posts.insert(local_feed_path, posts);
+        });
+
+        Ok(())
     }
 }
 
 pub enum Output {
     Html(String),
+    /// A URL to send the client to, after doing something that should not be
+    /// repeated if the client reloads the page.
+    Redirect(String),
+    Stylesheet(String),
+    Script(String),
+    /// Should be kept open, and sent to the client as they happen.
+    Events(Events),
 }
 
 impl core::fmt::Write for Output {
@@ -348,6 +1475,16 @@ impl core::fmt::Write for Output {
 
                 Ok(())
             }
+            Output::Stylesheet(ref mut output)
+            | Output::Script(ref mut output) => {
+                output.push_str(s);
+
+                Ok(())
+            }
+            // Writing the body of a redirect would be a bug.
+            Output::Redirect(_) => Err(core::fmt::Error),
+            // As would writing to the events, instead of streaming them.
+            Output::Events(_) => Err(core::fmt::Error),
         }
     }
 }
@@ -359,6 +1496,9 @@ type Flags = u8;
 const REFRESH_LOCAL: Flags       = 0b0000_0001;
 const REFRESH_REMOTE: Flags      = 0b0000_0010;
 const REFRESH_REMOTE_URLS: Flags = 0b0000_0100;
+/// Fetches the remote feeds without saving any that moved or are gone. Set by
+/// `Task::read_only`, rather than by a form.
+const REFRESH_WITHOUT_SAVING: Flags = 0b0000_1000;
 
 #[derive(Debug)]
 pub struct LocalAddForm {
@@ -371,13 +1511,66 @@ pub struct RemoteFeedAddForm {
     pub url: String,
 }
 
+#[derive(Debug)]
+pub struct RestoreForm {
+    pub path: LocalFeedPath,
+    /// Which backup to restore.
+    pub version: u64,
+}
+
 #[derive(Debug)]
 pub enum Task {
-    ShowHomePage(Flags),
+    ShowHomePage,
+    ShowStylesheet,
+    ShowKeyboardScript,
+    ShowLiveUpdatesScript,
+    ShowPosts,
+    ShowEvents,
+    Refresh(Flags),
+    ShowRefreshProgress,
+    CancelRefresh,
     ShowLocalAddForm,
     SubmitLocalAddForm(LocalAddForm),
     ShowRemoteFeedAddForm,
     SubmitRemoteFeedAddForm(RemoteFeedAddForm),
+    ShowRestorePage,
+    SubmitRestoreForm(RestoreForm),
+}
+
+impl Task {
+    /// Whether performing this task can change any files in the data directory,
+    /// or show a form that would do so. A refresh saves the remote feeds that
+    /// moved or are gone, unless it was made `read_only`.
+    pub fn writes(&self) -> bool {
+        use Task::*;
+
+        match self {
+            ShowHomePage
+            | ShowStylesheet
+            | ShowKeyboardScript
+            | ShowLiveUpdatesScript
+            | ShowPosts
+            | ShowEvents
+            | ShowRefreshProgress
+            | CancelRefresh => false,
+            Refresh(flags) => flags & REFRESH_WITHOUT_SAVING == 0,
+            ShowLocalAddForm
+            | SubmitLocalAddForm(_)
+            | ShowRemoteFeedAddForm
+            | SubmitRemoteFeedAddForm(_)
+            | ShowRestorePage
+            | SubmitRestoreForm(_) => true,
+        }
+    }
+
+    /// This task, changed to not write to any files if it can still do something
+    /// useful that way, for someone who is not allowed to change them.
+    pub fn read_only(self) -> Self {
+        match self {
+            Task::Refresh(flags) => Task::Refresh(flags | REFRESH_WITHOUT_SAVING),
+            task => task,
+        }
+    }
 }
 
 pub enum Method {
@@ -396,18 +1589,59 @@ impl core::fmt::Display for Method {
     }
 }
 
+/// What a request needs to include to show that it came from one of our own pages.
+pub enum Csrf<'token> {
+    /// State-changing requests must include this token as a form field.
+    Token(&'token str),
+    /// The request could not have been made by a browser on behalf of some other
+    /// site. For example, because it was authenticated with a bearer token, which
+    /// browsers do not add on their own.
+    NotNeeded,
+}
+
+impl Csrf<'_> {
+    pub fn check(&self, submitted: Option<&str>) -> Result<(), TaskError> {
+        match self {
+            Self::NotNeeded => Ok(()),
+            Self::Token(expected) => {
+                let matches = submitted.map(|submitted| {
+                    // Compared in a way that does not leak how many leading bytes
+                    // were correct through the timing.
+                    submitted.len() == expected.len()
+                    && submitted.bytes()
+                        .zip(expected.bytes())
+                        .fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+                }).unwrap_or(false);
+
+                if matches {
+                    Ok(())
+                } else {
+                    Err(TaskError("Missing or invalid CSRF token".to_owned()))
+                }
+            }
+        }
+    }
+}
+
 pub trait TaskSpec
 where
+    Self::RefreshFormError: std::error::Error,
     Self::LocalAddFormError: std::error::Error,
     Self::RemoteFeedAddFormError: std::error::Error,
+    Self::RestoreFormError: std::error::Error,
 {
     fn method(&self) -> Method;
     fn url_suffix(&self) -> String;
     fn query_param(&self, key: &str) -> Option<String>;
+    fn csrf(&self) -> Csrf<'_>;
+    type RefreshFormError;
+    fn refresh_form(&self) -> Result<Vec<(String, String)>, Self::RefreshFormError>;
     type LocalAddFormError;
     fn local_add_form(&self) -> Result<Vec<(String, String)>, Self::LocalAddFormError>;
     type RemoteFeedAddFormError;
     fn remote_feed_add_form(&self) -> Result<Vec<(String, String)>, Self::RemoteFeedAddFormError>;
+    type RestoreFormError;
+    fn restore_form(&self) -> Result<Vec<(String, String)>, Self::RestoreFormError>;
 }
 
 #[derive(Debug)]
@@ -432,20 +1666,83 @@ pub fn extract_task(
     let url = spec.url_suffix();
     match (spec.method(), url.as_ref()) {
         (Method::Get, "/") => {
-            let mut flags = 0;
-            if let Some(_) = spec.query_param(param_keys::REFRESH_LOCAL) {
-                flags |= REFRESH_LOCAL;
-            }
+            Ok(ShowHomePage)
+        },
+        (Method::Get, page_names::STYLESHEET) => {
+            Ok(ShowStylesheet)
+        },
+        (Method::Get, page_names::KEYBOARD_SCRIPT) => {
+            Ok(ShowKeyboardScript)
+        },
+        (Method::Get, page_names::LIVE_SCRIPT) => {
+            Ok(ShowLiveUpdatesScript)
+        },
+        (Method::Get, page_names::POSTS) => {
+            Ok(ShowPosts)
+        },
+        (Method::Get, page_names::EVENTS) => {
+            Ok(ShowEvents)
+        },
+        (Method::Post, "/") => {
+            spec.refresh_form()
+                .map_err(|e| TaskError(e.to_string()))
+                .and_then(|pairs| {
+                    let mut flags = 0;
+                    let mut csrf_token = None;
 
-            if let Some(_) = spec.query_param(param_keys::REFRESH_REMOTE) {
-                flags |= REFRESH_REMOTE;
-            }
+                    for (k, v) in pairs {
+                        match k.as_str() {
+                            param_keys::REFRESH_LOCAL => {
+                                flags |= REFRESH_LOCAL;
+                            }
+                            param_keys::REFRESH_REMOTE => {
+                                flags |= REFRESH_REMOTE;
+                            }
+                            param_keys::REFRESH_REMOTE_URLS => {
+                                flags |= REFRESH_REMOTE_URLS;
+                            }
+                            form_names::CSRF_TOKEN => {
+                                csrf_token = Some(v);
+                            }
+                            _ => {
+                                return Err(TaskError(format!(
+                                    "Unhandled Form pair ({k}, {v})"
+                                )))
+                            }
+                        }
+                    }
 
-            if let Some(_) = spec.query_param(param_keys::REFRESH_REMOTE_URLS) {
-                flags |= REFRESH_REMOTE_URLS;
-            }
+                    spec.csrf().check(csrf_token.as_deref())?;
+
+                    Ok(Refresh(flags))
+                })
+        },
+        (Method::Get, page_names::REFRESH) => {
+            Ok(ShowRefreshProgress)
+        },
+        (Method::Post, page_names::REFRESH_CANCEL) => {
+            spec.refresh_form()
+                .map_err(|e| TaskError(e.to_string()))
+                .and_then(|pairs| {
+                    let mut csrf_token = None;
+
+                    for (k, v) in pairs {
+                        match k.as_str() {
+                            form_names::CSRF_TOKEN => {
+                                csrf_token = Some(v);
+                            }
+                            _ => {
+                                return Err(TaskError(format!(
+                                    "Unhandled Form pair ({k}, {v})"
+                                )))
+                            }
+                        }
+                    }
+
+                    spec.csrf().check(csrf_token.as_deref())?;
 
-            Ok(ShowHomePage(flags))
+                    Ok(CancelRefresh)
+                })
         },
         (Method::Get, page_names::LOCAL_ADD) => {
             Ok(ShowLocalAddForm)
@@ -455,6 +1752,7 @@ pub fn extract_task(
                 .map_err(|e| TaskError(e.to_string()))
                 .and_then(|pairs| {
                     let mut path = PathBuf::default();
+                    let mut csrf_token = None;
 
                     let mut title = None;
                     let mut summary = None;
@@ -474,6 +1772,9 @@ pub fn extract_task(
                             form_names::TARGET => {
                                 path = PathBuf::from(v);
                             }
+                            form_names::CSRF_TOKEN => {
+                                csrf_token = Some(v);
+                            }
                             form_names::TITLE => {
                                 title = Some(v);
                             }
@@ -497,6 +1798,8 @@ pub fn extract_task(
                         }
                     }
 
+                    spec.csrf().check(csrf_token.as_deref())?;
+
                     let path = LocalFeedPath::new(
                         path,
                         &state.local_feeds_dir
@@ -507,10 +1810,13 @@ pub fn extract_task(
                     Ok(SubmitLocalAddForm(LocalAddForm {
                         path,
                         post: Post {
+                            id: None,
                             title,
                             summary,
                             content,
                             links,
+                            published: None,
+                            updated: None,
                         },
                     }))
                 })
@@ -523,6 +1829,7 @@ pub fn extract_task(
                 .map_err(|e| TaskError(e.to_string()))
                 .and_then(|pairs| {
                     let mut url = String::new();
+                    let mut csrf_token = None;
 
                     for (k, v) in pairs {
                         if v.is_empty() {
@@ -533,6 +1840,9 @@ pub fn extract_task(
                             form_names::FEED_URL => {
                                 url = v;
                             }
+                            form_names::CSRF_TOKEN => {
+                                csrf_token = Some(v);
+                            }
                             _ => {
                                 return Err(TaskError(format!(
                                     "Unhandled Form pair ({k}, {v})"
@@ -541,11 +1851,62 @@ pub fn extract_task(
                         }
                     }
 
+                    spec.csrf().check(csrf_token.as_deref())?;
+
                     Ok(SubmitRemoteFeedAddForm(RemoteFeedAddForm {
                         url,
                     }))
                 })
         },
+        (Method::Get, page_names::RESTORE) => {
+            Ok(ShowRestorePage)
+        },
+        (Method::Post, page_names::RESTORE) => {
+            spec.restore_form()
+                .map_err(|e| TaskError(e.to_string()))
+                .and_then(|pairs| {
+                    let mut path = PathBuf::default();
+                    let mut version = None;
+                    let mut csrf_token = None;
+
+                    for (k, v) in pairs {
+                        match k.as_str() {
+                            form_names::TARGET => {
+                                path = PathBuf::from(v);
+                            }
+                            form_names::VERSION => {
+                                version = Some(v);
+                            }
+                            form_names::CSRF_TOKEN => {
+                                csrf_token = Some(v);
+                            }
+                            _ => {
+                                return Err(TaskError(format!(
+                                    "Unhandled Form pair ({k}, {v})"
+                                )))
+                            }
+                        }
+                    }
+
+                    spec.csrf().check(csrf_token.as_deref())?;
+
+                    let path = LocalFeedPath::new(
+                        path,
+                        &state.local_feeds_dir
+                    ).map_err(|e|
+                        TaskError(format!("{e}"))
+                    )?;
+
+                    let version = version
+                        .and_then(|v| v.parse().ok())
+                        .ok_or_else(|| TaskError("Missing or invalid version".to_owned()))?;
+
+                    Ok(SubmitRestoreForm(RestoreForm {
+                        path,
+                        version,
+                    }))
+                })
+        },
         (method, _) => {
             Err(TaskError(
                 format!(
@@ -559,7 +1920,7 @@ pub fn extract_task(
 #[derive(Debug)]
 pub enum PerformError {
     Io(std::io::Error),
-    Fetch(fetch::Error),
+    FailedFeeds(FailedFeeds),
     Render(render::Error),
     MissingLocalFile,
     UrlParse(fetch::UrlParseError),
@@ -569,7 +1930,7 @@ impl core::fmt::Display for PerformError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::Io(e) => write!(f, "{e}"),
-            Self::Fetch(e) => write!(f, "{e}"),
+            Self::FailedFeeds(e) => write!(f, "{e}"),
             Self::Render(e) => write!(f, "{e}"),
             Self::MissingLocalFile => write!(f, "Local file did not exist"),
             Self::UrlParse(e) => write!(f, "{e}"),
@@ -579,13 +1940,9 @@ impl core::fmt::Display for PerformError {
 
 impl std::error::Error for PerformError {}
 
-impl From<FetchRemoteFeedsError> for PerformError {
-    fn from(e: FetchRemoteFeedsError) -> Self {
-        use FetchRemoteFeedsError as E;
-        match e {
-            E::Io(e) => Self::Io(e),
-            E::Fetch(e) => Self::Fetch(e),
-        }
+impl From<FailedFeeds> for PerformError {
+    fn from(e: FailedFeeds) -> Self {
+        Self::FailedFeeds(e)
     }
 }
 
@@ -612,72 +1969,281 @@ impl From<render::Error> for PerformError {
 }
 
 impl State {
-    pub fn perform(&mut self, task: Task) -> Result<Output, PerformError> {
+    /// `previous` is the previously submitted user name, and an error message.
+    pub fn login_page(
+        &self,
+        previous: Option<(&str, &str)>,
+    ) -> Result<Output, PerformError> {
+        let mut output = Output::Html(String::with_capacity(4096));
+
+        render::login_page(
+            &mut output,
+            &self.preferences(&self.snapshot()),
+            previous.map(|(name, error)| (render::LoginForm { name }, error)),
+        )?;
+
+        Ok(output)
+    }
+
+    /// Fetches the remote feeds without holding up anything else, then publishes
+    /// the results.
+    fn fetch_remote(&self, saves: bool) -> Result<(), FailedFeeds> {
+        let job = RefreshJob::new(&self.snapshot(), saves);
+
+        job.run(self);
+
+        job.failed_feeds()
+    }
+
+    /// For the files directly in the data directory.
+    fn backups(&self) -> write_atomically::Backups {
+        write_atomically::Backups {
+            dir: self.root.path_to(BACKUPS),
+            keep: self.config.backups.keep,
+        }
+    }
+
+    /// Kept apart from the other backups, so a local feed can have the same name
+    /// as one of the other files.
+    fn local_feed_backups(&self) -> write_atomically::Backups {
+        write_atomically::Backups {
+            dir: self.root.path_to(BACKUPS).join(LOCAL_FEEDS),
+            keep: self.config.backups.keep,
+        }
+    }
+
+    fn lock_failing_feeds(&self) -> MutexGuard<'_, HashMap<Url, FailingFeed>> {
+        // Each entry is replaced whole, so a panic while holding this cannot leave
+        // things half-updated.
+        self.failing_feeds.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn refresh_by_flags(&self, flags: Flags) -> Result<(), PerformError> {
+        if flags & REFRESH_LOCAL != 0 {
+            self.update(|snapshot| {
+                snapshot.stylesheet = Stylesheet::load(&self.root)?;
+
+                load_local_posts(
+                    &mut snapshot.local_posts,
+                    &self.local_feeds_dir,
+                    &self.time_zone,
+                )
+            })?;
+        }
+
+        if flags & REFRESH_REMOTE != 0 {
+            self.fetch_remote(flags & REFRESH_WITHOUT_SAVING == 0)?;
+        }
+
+        if flags & REFRESH_REMOTE_URLS != 0 {
+            let mut remote_feeds_file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(self.root.path_to(REMOTE_FEEDS))?;
+
+            self.update(|snapshot| {
+                load_remote_feed_urls(
+                    &mut remote_feeds_file,
+                    &mut snapshot.remote_posts,
+                )?;
+
+                snapshot.gone_feeds = load_gone_feeds(&self.root)?;
+
+                Ok::<_, RemoteFeedUrlsError>(())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts fetching the remote feeds on another thread, unless that is
+    /// already happening.
+    fn start_refresh_job(self: &Arc<Self>, saves: bool) {
+        let mut refresh_job = self.refresh_job.lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        if let Some(job) = refresh_job.as_ref() {
+            if !job.finished.load(Ordering::Acquire) {
+                return
+            }
+        }
+
+        let job = Arc::new(RefreshJob::new(&self.snapshot(), saves));
+
+        *refresh_job = Some(Arc::clone(&job));
+
+        let state = Arc::clone(self);
+        std::thread::spawn(move || job.run(&state));
+    }
+
+    fn refresh_job(&self) -> Option<Arc<RefreshJob>> {
+        self.refresh_job.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// `csrf_token` will be included in any forms that are rendered, so it should
+    /// be the one that `extract_task` expects for the next request.
+    pub fn perform(
+        self: &Arc<Self>,
+        task: Task,
+        csrf_token: &str,
+    ) -> Result<Output, PerformError> {
         use Task::*;
 
         // 64k ought to be enough for anybody!
         let mut output = Output::Html(String::with_capacity(65536));
 
         macro_rules! data {
-            () => {
+            ($snapshot: expr) => {
                 &Data {
                     root: &self.root,
-                    local_posts: &self.local_posts,
-                    remote_posts: &self.remote_posts,
+                    local_posts: &$snapshot.local_posts,
+                    remote_posts: &$snapshot.remote_posts,
+                    gone_feeds: &$snapshot.gone_feeds,
+                    now: Timestamp::now_in(&self.time_zone),
                 }
             }
         }
 
+        // Markdown feeds get posts added by adding files, not through the form.
+        macro_rules! targets {
+            ($paths: expr) => {
+                $paths
+                    .filter(|path| !path.as_ref().is_dir())
+                    .map(|path| Target {
+                        path: path.as_ref(),
+                        root: &self.root,
+                    })
+            }
+        }
+
         match task {
-            ShowHomePage(flags) => {
-                if flags & REFRESH_LOCAL != 0 {
-                    load_local_posts(
-                        &mut self.local_posts,
-                        &self.local_feeds_dir,
-                        self.utc_offset,
-                    )?;
-                }
+            ShowHomePage => {
+                let snapshot = self.snapshot();
 
-                if flags & REFRESH_REMOTE != 0 {
-                    fetch_remote_feeds(
-                        &mut self.remote_posts,
-                        self.utc_offset,
-                    )?;
-                }
+                render::home_page(
+                    &mut output,
+                    &self.preferences(&snapshot),
+                    csrf_token,
+                    data!(snapshot),
+                )?;
+            },
+            ShowStylesheet => {
+                output = Output::Stylesheet(self.snapshot().stylesheet.css.clone());
+            },
+            ShowKeyboardScript => {
+                output = Output::Script(
+                    render::KEYBOARD_SHORTCUTS_SCRIPT.to_owned()
+                );
+            },
+            ShowLiveUpdatesScript => {
+                output = Output::Script(
+                    render::LIVE_UPDATES_SCRIPT.to_owned()
+                );
+            },
+            ShowPosts => {
+                let snapshot = self.snapshot();
+
+                render::posts_fragment(
+                    &mut output,
+                    &self.preferences(&snapshot),
+                    data!(snapshot),
+                )?;
+            },
+            ShowEvents => {
+                output = Output::Events(self.subscribe());
+            },
+            Refresh(flags) => {
+                // Fetching the remote feeds can take a while, so we do that in the
+                // background, and show the progress instead.
+                self.refresh_by_flags(flags & !REFRESH_REMOTE)?;
 
-                if flags & REFRESH_REMOTE_URLS != 0 {
-                    let mut remote_feeds_file = std::fs::OpenOptions::new()
-                        .read(true)
-                        .write(true)
-                        .create(true)
-                        .open(self.root.path_to(REMOTE_FEEDS))?;
+                output = Output::Redirect(
+                    if flags & REFRESH_REMOTE != 0 {
+                        self.start_refresh_job(flags & REFRESH_WITHOUT_SAVING == 0);
 
-                    load_remote_feed_urls(
-                        &mut remote_feeds_file,
-                        &mut self.remote_posts,
-                    )?;
+                        page_names::REFRESH
+                    } else {
+                        "/"
+                    }.to_owned()
+                );
+            },
+            ShowRefreshProgress => {
+                let Some(job) = self.refresh_job() else {
+                    return Ok(Output::Redirect("/".to_owned()))
+                };
+
+                let finished = job.finished.load(Ordering::Acquire);
+                let cancelled = job.cancelled.load(Ordering::Acquire);
+
+                let feeds = job.lock_feeds();
+
+                let job_status = match (finished, cancelled) {
+                    (false, false) => render::RefreshJobStatus::Running,
+                    (false, true) => render::RefreshJobStatus::Cancelling,
+                    (true, false) => render::RefreshJobStatus::Finished,
+                    (true, true) => render::RefreshJobStatus::Cancelled,
+                };
+
+                let failed = feeds.iter()
+                    .any(|(_, status)| matches!(
+                        status,
+                        FeedRefreshStatus::Failed(_) | FeedRefreshStatus::Gone
+                    ));
+
+                // Any failures are worth stopping to look at, but otherwise
+                // there's nothing more to see here.
+                if let (render::RefreshJobStatus::Finished, false) = (&job_status, failed) {
+                    return Ok(Output::Redirect("/".to_owned()))
                 }
 
-                render::home_page(
+                render::refresh_progress_page(
                     &mut output,
-                    data!(),
+                    &self.preferences(&self.snapshot()),
+                    csrf_token,
+                    job_status,
+                    feeds.iter().map(|(o_url, status)| {
+                        use render::FeedRefreshStatus as R;
+
+                        (
+                            o_url.url.as_str(),
+                            match status {
+                                FeedRefreshStatus::Pending => R::Pending,
+                                FeedRefreshStatus::InFlight => R::InFlight,
+                                FeedRefreshStatus::Done => R::Done,
+                                FeedRefreshStatus::Moved(to) => R::Moved(to.as_str()),
+                                FeedRefreshStatus::Failed(e) => R::Failed(e),
+                                FeedRefreshStatus::BackingOff { failures } => {
+                                    R::BackingOff { failures: *failures }
+                                }
+                                FeedRefreshStatus::Gone => R::Gone,
+                            }
+                        )
+                    }),
                 )?;
             },
+            CancelRefresh => {
+                if let Some(job) = self.refresh_job() {
+                    job.cancelled.store(true, Ordering::Release);
+                }
+
+                output = Output::Redirect(page_names::REFRESH.to_owned());
+            },
             ShowLocalAddForm => {
-                load_local_feed_paths(
-                    &mut self.local_posts,
-                    &self.local_feeds_dir
-                )?;
+                // Listed from the directory rather than the loaded posts, so
+                // that feeds added since then can be picked.
+                let paths = local_feed_paths(&self.local_feeds_dir)?;
+                let snapshot = self.snapshot();
 
                 render::local_add_form(
                     &mut output,
-                    self.local_posts
-                        .keys()
-                        .map(|path| Target {
-                            path: path.as_ref(),
-                            root: &self.root,
-                        }),
-                    data!(),
+                    &self.preferences(&snapshot),
+                    csrf_token,
+                    targets!(paths.iter()),
+                    data!(snapshot),
                     Option::<(
                         render::LocalAddForm<'_, '_, '_, '_, Target<'_, '_>, String>,
                         &str
@@ -685,40 +2251,56 @@ impl State {
                 )?;
             }
             SubmitLocalAddForm(form) => {
-                match add_local_post(
-                    self.local_posts
-                        .get_mut(&form.path)
-                        .ok_or(PerformError::MissingLocalFile)?,
-                    form,
-                    self.utc_offset,
-                ) {
+                self.update(|snapshot| load_new_local_feed(
+                    &mut snapshot.local_posts,
+                    &form.path,
+                    &self.time_zone,
+                ))?;
+
+                if !self.snapshot().local_posts.contains_key(&form.path) {
+                    return Err(PerformError::MissingLocalFile)
+                }
+
+                // See `add_local_post` for why this is allowed.
+                #[allow(clippy::result_large_err)]
+                let result = self.update(|snapshot| {
+                    match snapshot.local_posts.get_mut(&form.path) {
+                        Some(posts) => add_local_post(
+                            posts,
+                            form,
+                            &self.time_zone,
+                            &self.local_feed_backups(),
+                        ),
+                        // Someone removed it since we checked above.
+                        None => Err((form, Box::from("Local file did not exist"))),
+                    }
+                });
+
+                let snapshot = self.snapshot();
+                let preferences = &self.preferences(&snapshot);
+
+                match result {
                     Ok(()) => {
-                        render::local_add_form_success(&mut output)?
+                        render::local_add_form_success(&mut output, preferences)?
                     }
                     Err((form, e)) => {
                         render::local_add_form(
                             &mut output,
-                            self.local_posts
-                                .keys()
-                                .map(|path| Target {
-                                    path: path.as_ref(),
-                                    root: &self.root,
-                                }),
-                            data!(),
+                            preferences,
+                            csrf_token,
+                            targets!(local_feed_paths(&self.local_feeds_dir)?.iter()),
+                            data!(snapshot),
                             Some((
                                 render::LocalAddForm {
                                     target: Target{
                                         path: form.path.as_ref(),
                                         root: &self.root,
                                     },
-                                    title: form.post.title.as_ref()
-                                        .map(|s| s.as_str())
+                                    title: form.post.title.as_deref()
                                         .unwrap_or_default(),
-                                    summary: form.post.summary.as_ref()
-                                        .map(|s| s.as_str())
+                                    summary: form.post.summary.as_deref()
                                         .unwrap_or_default(),
-                                    content: form.post.content.as_ref()
-                                        .map(|s| s.as_str())
+                                    content: form.post.content.as_deref()
                                         .unwrap_or_default(),
                                     links: &form.post.links,
                                 },
@@ -729,23 +2311,35 @@ impl State {
                 }
             }
             ShowRemoteFeedAddForm => {
+                let snapshot = self.snapshot();
+
                 render::remote_feed_add_form(
                     &mut output,
-                    data!(),
+                    &self.preferences(&snapshot),
+                    csrf_token,
+                    data!(snapshot),
                     Option::<(render::RemoteFeedAddForm<'_>, &str)>::None,
                 )?;
             }
             SubmitRemoteFeedAddForm(form) => {
-                match add_remote_feed(
-                    &mut self.remote_posts,
+                let result = self.update(|snapshot| add_remote_feed(
+                    &mut snapshot.remote_posts,
                     form,
                     &self.root,
-                ) {
-                    Ok(()) => render::remote_feed_add_form_success(&mut output)?,
+                    &self.backups(),
+                ));
+
+                let snapshot = self.snapshot();
+                let preferences = &self.preferences(&snapshot);
+
+                match result {
+                    Ok(()) => render::remote_feed_add_form_success(&mut output, preferences)?,
                     Err((form, e)) => {
                         render::remote_feed_add_form(
                             &mut output,
-                            data!(),
+                            preferences,
+                            csrf_token,
+                            data!(snapshot),
                             Some((
                                 render::RemoteFeedAddForm {
                                     url: form.url.as_ref()
@@ -756,16 +2350,132 @@ impl State {
                     }
                 }
             }
+            ShowRestorePage => {
+                let snapshot = self.snapshot();
+
+                self.restore_page(&mut output, csrf_token, data!(snapshot), None)?;
+            }
+            SubmitRestoreForm(form) => {
+                self.update(|snapshot| load_new_local_feed(
+                    &mut snapshot.local_posts,
+                    &form.path,
+                    &self.time_zone,
+                ))?;
+
+                let result = self.update(|snapshot| {
+                    match snapshot.local_posts.get_mut(&form.path) {
+                        Some(posts) => restore_local_feed(
+                            posts,
+                            &form,
+                            &self.time_zone,
+                            &self.local_feed_backups(),
+                        ),
+                        None => Err(Box::from("Local file did not exist")),
+                    }
+                });
+
+                let snapshot = self.snapshot();
+
+                match result {
+                    Ok(()) => {
+                        use render::Target as _;
+
+                        render::restore_success(
+                            &mut output,
+                            &self.preferences(&snapshot),
+                            Target {
+                                path: form.path.as_ref(),
+                                root: &self.root,
+                            }.label(),
+                        )?
+                    }
+                    Err(e) => self.restore_page(
+                        &mut output,
+                        csrf_token,
+                        data!(snapshot),
+                        Some(&e.to_string()),
+                    )?,
+                }
+            }
         }
 
         Ok(output)
     }
+
+    fn restore_page(
+        &self,
+        output: &mut Output,
+        csrf_token: &str,
+        root_display: &impl render::RootDisplay,
+        error_message: Option<&str>,
+    ) -> Result<(), PerformError> {
+        let snapshot = self.snapshot();
+        let backups = self.local_feed_backups();
+        let now = Timestamp::now_in(&self.time_zone);
+
+        // Listed from the directory, so that feeds added since the posts were
+        // last loaded show up, without having to load them.
+        let paths = local_feed_paths(&self.local_feeds_dir)?;
+        let mut feeds = Vec::with_capacity(paths.len());
+
+        // Markdown feeds are never overwritten, so they have nothing to restore.
+        for path in paths.iter().filter(|path| !path.as_ref().is_dir()) {
+            feeds.push((path, write_atomically::backups(path, &backups)?));
+        }
+
+        render::restore_page(
+            output,
+            &self.preferences(&snapshot),
+            csrf_token,
+            feeds.iter().map(|(path, versions)| render::BackedUpFeed {
+                target: Target {
+                    path: path.as_ref(),
+                    root: &self.root,
+                },
+                versions: versions.iter().map(|backup| render::Version {
+                    id: backup.id(),
+                    replaced_at: Time {
+                        at: Timestamp::from(backup.replaced_at())
+                            .in_zone(&self.time_zone),
+                        now,
+                    },
+                }),
+            }),
+            root_display,
+            error_message,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Puts a previous version of a local feed back, then reloads its posts.
+fn restore_local_feed(
+    posts: &mut Posts,
+    form: &RestoreForm,
+    time_zone: &TimeZone,
+    backups: &write_atomically::Backups,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let backup = write_atomically::backups(&form.path, backups)?
+        .into_iter()
+        .find(|backup| backup.id() == form.version)
+        .ok_or("That version is no longer available")?;
+
+    write_atomically::restore(&form.path, &backup, backups)?;
+
+    load_local_feed(posts, form.path.as_ref(), time_zone)?;
+
+    Ok(())
 }
 
+// We hand the form back on failure so it can be re-displayed, so the error
+// variant is as large as the form.
+#[allow(clippy::result_large_err)]
 fn add_local_post(
     posts: &mut Posts,
     form: LocalAddForm,
-    utc_offset: UtcOffset,
+    time_zone: &TimeZone,
+    backups: &write_atomically::Backups,
 ) -> Result<(), (LocalAddForm, Box<dyn std::error::Error>)> {
     // `q` is short for "question mark" since this is like `?`.
     macro_rules! q {
@@ -777,27 +2487,34 @@ fn add_local_post(
         }
     }
 
+    if form.path.as_ref().is_dir() {
+        return Err((
+            form,
+            Box::from("Posts cannot be added to Markdown feeds. Add a file to the directory instead."),
+        ))
+    }
+
     {
-        let buffer = q!(std::fs::read_to_string(&form.path));
+        let bytes = q!(std::fs::read(&form.path));
+        let buffer = syndicated::decode(&bytes, None);
 
-        q!(write_atomically::write_atomically(
+        // Since the post is added by re-writing the whole feed, this also
+        // converts the file to UTF-8.
+        q!(write_atomically::write_atomically_if_unchanged(
             &form.path,
+            &bytes,
+            backups,
             |file| syndicated::add_post(
                 file,
                 std::io::Cursor::new(&buffer),
                 form.post.clone(),
+                Timestamp::now_in(time_zone),
             )
         ));
     }
 
-    let buffer = q!(std::fs::read_to_string(&form.path));
-
-    load_local_post_from_buffer(
-        posts,
-        std::io::Cursor::new(&buffer),
-        utc_offset,
-    );
-
+    q!(load_local_feed(posts, form.path.as_ref(), time_zone));
+
     Ok(())
 }
 
@@ -805,8 +2522,9 @@ fn add_remote_feed(
     remote_posts: &mut RemotePosts,
     form: RemoteFeedAddForm,
     root: &Root,
+    backups: &write_atomically::Backups,
 ) -> Result<(), (RemoteFeedAddForm, Box<dyn std::error::Error>)> {
-    use std::io::{Read, Seek, Write};
+    use std::io::Write;
 
     // `q` is short for "question mark" since this is like `?`.
     macro_rules! q {
@@ -818,41 +2536,180 @@ fn add_remote_feed(
         }
     }
 
-    let url = q!(Url::parse(form.url.as_ref()));
+    let url = q!(parse_remote_feed_url(form.url.as_ref()));
+
+    // Anyone who could add one of these could run anything they like, or read
+    // any file we can, so they have to be added by editing the file, which needs
+    // access to it anyway.
+    match url.scheme() {
+        EXEC_SCHEME => return Err((
+            form,
+            Box::from("Command feeds can only be added by editing the remote-feeds file"),
+        )),
+        "file" => return Err((
+            form,
+            Box::from("Local file feeds can only be added by editing the remote-feeds file"),
+        )),
+        _ => {}
+    }
 
-    let mut remote_feeds_file = q!(std::fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .append(true)
-        .open(root.path_to(REMOTE_FEEDS)));
+    let path = root.path_to(REMOTE_FEEDS);
 
-    // Read the last byte of the file, if there is one, and if it is not a newline
-    // character, add one.
-    let end = q!(remote_feeds_file.seek(std::io::SeekFrom::End(0)));
-    if end != 0 {
-        q!(remote_feeds_file.seek(std::io::SeekFrom::End(-1)));
+    let existing = match std::fs::read(&path) {
+        Ok(existing) => existing,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err((form, Box::from(e))),
+    };
 
-        let byte = &mut [0];
-        q!(remote_feeds_file.read(byte));
+    let mut updated = existing.clone();
 
-        if byte[0] != b'\n' {
-            q!(writeln!(remote_feeds_file));
-        }
+    // The last line might not have a newline after it.
+    if updated.last().is_some_and(|&byte| byte != b'\n') {
+        updated.push(b'\n');
     }
 
-    q!(writeln!(remote_feeds_file, "{url}"));
-
-    q!(remote_feeds_file.flush());
+    q!(writeln!(updated, "{url}"));
 
-    q!(remote_feeds_file.seek(std::io::SeekFrom::Start(0)));
+    q!(write_atomically::write_atomically_if_unchanged(
+        &path,
+        &existing,
+        backups,
+        |file| file.write_all(&updated),
+    ));
 
     load_remote_feed_urls(
-        &mut remote_feeds_file,
+        &mut updated.as_slice(),
         remote_posts,
     ).map_err(|e| (form, Box::from(e)))
 }
 
+/// Operations that do not go through a `Task`, for use outside of a server.
+impl State {
+    pub fn remote_feed_urls(&self) -> Vec<Url> {
+        self.snapshot()
+            .remote_posts
+            .keys()
+            .map(|o_url| o_url.url.clone())
+            .collect()
+    }
+
+    /// The paths are relative to the root.
+    pub fn local_feed_paths(&self) -> Vec<String> {
+        use render::Target as _;
+
+        self.snapshot()
+            .local_posts
+            .keys()
+            .map(|path| Target {
+                path: path.as_ref(),
+                root: &self.root,
+            }.label().to_string())
+            .collect()
+    }
+
+    /// Returns the number of (local, remote) posts currently loaded.
+    pub fn post_counts(&self) -> (usize, usize) {
+        let snapshot = self.snapshot();
+        let count = |posts: &Posts| posts.posts.len();
+
+        (
+            snapshot.local_posts.values().map(count).sum(),
+            snapshot.remote_posts.values().map(count).sum(),
+        )
+    }
+
+    pub fn fetch_remote_posts(&self) -> Result<(), PerformError> {
+        self.refresh_by_flags(REFRESH_REMOTE)
+    }
+
+    /// Reloads the remote feed URLs and the local posts, then fetches the remote
+    /// posts.
+    pub fn refresh(&self) -> Result<(), PerformError> {
+        self.refresh_by_flags(REFRESH_LOCAL | REFRESH_REMOTE_URLS)?;
+
+        // We want the reloaded URLs to be fetched, so this needs to happen after
+        // the above.
+        self.refresh_by_flags(REFRESH_REMOTE)
+    }
+
+    pub fn add_remote_feed(
+        &self,
+        url: String
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.update(|snapshot| add_remote_feed(
+            &mut snapshot.remote_posts,
+            RemoteFeedAddForm { url },
+            &self.root,
+            &self.backups(),
+        )).map_err(|(_, e)| e)
+    }
+
+    /// `feed` is relative to the local feeds directory, unless it is absolute.
+    pub fn add_local_post(
+        &self,
+        feed: impl AsRef<Path>,
+        post: Post,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let path = LocalFeedPath::new(
+            self.local_feeds_dir.0.join(feed),
+            &self.local_feeds_dir,
+        ).map_err(|e| e.to_string())?;
+
+        self.update(|snapshot| {
+            let posts = snapshot.local_posts
+                .get_mut(&path)
+                .ok_or(PerformError::MissingLocalFile)?;
+
+            add_local_post(
+                posts,
+                LocalAddForm { path, post },
+                &self.time_zone,
+                &self.local_feed_backups(),
+            ).map_err(|(_, e)| e)
+        })
+    }
+
+    /// Writes the remote feed URLs out as an OPML subscription list.
+    pub fn export_opml(&self, mut w: impl std::io::Write) -> std::io::Result<()> {
+        writeln!(w, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(w, r#"<opml version="2.0">"#)?;
+        writeln!(w, "<head><title>RRR Subscriptions</title></head>")?;
+        writeln!(w, "<body>")?;
+
+        for url in self.remote_feed_urls() {
+            // Other readers would not be able to do anything with a command.
+            if url.scheme() == EXEC_SCHEME {
+                continue
+            }
+
+            let url = XmlEscaped(url.as_str());
+            writeln!(w, r#"<outline type="rss" text="{url}" xmlUrl="{url}"/>"#)?;
+        }
+
+        writeln!(w, "</body>")?;
+        writeln!(w, "</opml>")
+    }
+}
+
+struct XmlEscaped<'s>(&'s str);
+
+impl core::fmt::Display for XmlEscaped<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&apos;")?,
+                _ => core::fmt::Write::write_char(f, c)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
 fn ensure_directory(path: impl AsRef<Path>) -> std::io::Result<PathBuf> {
     let path = path.as_ref();
     std::fs::create_dir_all(path)?;
@@ -866,6 +2723,9 @@ struct Data<'root, 'posts> {
     root: &'root Root,
     local_posts: &'posts LocalPosts,
     remote_posts: &'posts RemotePosts,
+    gone_feeds: &'posts BTreeSet<Url>,
+    /// What times are shown relative to.
+    now: Timestamp,
 }
 
 impl <'root> render::RootDisplay for Data<'root, '_> {
@@ -876,7 +2736,7 @@ impl <'root> render::RootDisplay for Data<'root, '_> {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 enum Source<'a> {
     Url(&'a Url),
     LocalFeedPath(&'a LocalFeedPath),
@@ -891,14 +2751,49 @@ impl core::fmt::Display for Source<'_> {
     }
 }
 
+/// A post, along with every feed that it showed up in.
 struct PostHolder<'posts> {
     post: &'posts Post,
-    source: Source<'posts>,
+    /// Never empty. The first one is where `post` came from.
+    sources: Vec<Source<'posts>>,
+    now: Timestamp,
+}
+
+/// Displays as a valid HTML id.
+struct PostId(u64);
+
+impl core::fmt::Display for PostId {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "p{:016x}", self.0)
+    }
 }
 
 impl <'posts> render::PostHolder for PostHolder<'posts> {
+    type Id = PostId;
     type Link = String;
     type Source = Source<'posts>;
+    type Sources = std::vec::IntoIter<Source<'posts>>;
+    type Time = Time;
+
+    fn id(&self) -> Self::Id {
+        let post = self.post;
+
+        // Feeds are supposed to provide ids, but we fall back to other things
+        // that are likely to stay the same, in case they don't.
+        let key = post.id.as_deref()
+            .or_else(|| post.links.first().map(|s| s.as_str()))
+            .or(post.title.as_deref())
+            .unwrap_or_default();
+
+        // Based on only the first source, so that the post keeps its id when
+        // copies of it show up in feeds that come after that one.
+        let source = self.sources[0].to_string();
+
+        PostId(
+            stable_hash(source.as_bytes())
+            ^ stable_hash(key.as_bytes()).rotate_left(1)
+        )
+    }
 
     fn get_post(&self) -> render::Post<'_, String> {
         render::Post {
@@ -909,9 +2804,130 @@ impl <'posts> render::PostHolder for PostHolder<'posts> {
         }
     }
 
-    fn source(&self) -> Self::Source {
-        self.source
+    fn sources(&self) -> Self::Sources {
+        self.sources.clone().into_iter()
+    }
+
+    fn time(&self) -> Option<Self::Time> {
+        self.post.date().map(|at| Time { at, now: self.now })
+    }
+}
+
+/// Something that, if two posts share it, means they are the same post.
+#[derive(PartialEq, Eq, Hash)]
+enum DuplicateKey<'post> {
+    Id(&'post str),
+    Link(String),
+    Content(&'post str),
+}
+
+fn duplicate_keys(post: &Post) -> Vec<DuplicateKey<'_>> {
+    let mut keys = Vec::with_capacity(3);
+
+    // Ids are only unique within a feed, unless they look like URIs, as Atom ids
+    // are supposed to be. Plenty of RSS feeds just number their items.
+    if let Some(id) = post.id.as_deref().filter(|id| id.contains(':')) {
+        keys.push(DuplicateKey::Id(id));
+    }
+
+    if let Some(link) = post.links.first() {
+        keys.push(DuplicateKey::Link(canonical_link(link)));
+    }
+
+    if let Some(content) = post.content.as_deref()
+        .map(str::trim)
+        .filter(|content| !content.is_empty()) {
+        keys.push(DuplicateKey::Content(content));
+    }
+
+    keys
+}
+
+/// Query parameters that only say how someone got to a page, and so differ
+/// between copies of a link that are otherwise the same.
+fn is_tracking_param(key: &str) -> bool {
+    key.starts_with("utm_")
+    || matches!(
+        key,
+        "fbclid" | "gclid" | "dclid" | "msclkid" | "mc_cid" | "mc_eid" | "igshid"
+    )
+}
+
+/// A form of `link` that is the same for links that lead to the same page,
+/// ignoring the scheme, the case of the host, a trailing slash, the fragment and
+/// tracking parameters.
+fn canonical_link(link: &str) -> String {
+    let Ok(url) = Url::parse(link.trim()) else {
+        return link.trim().to_owned()
+    };
+
+    let mut canonical = String::with_capacity(link.len());
+
+    // `Url` has already lowercased the host, and dropped default ports.
+    canonical.push_str(url.host_str().unwrap_or_default());
+    if let Some(port) = url.port() {
+        canonical.push_str(&format!(":{port}"));
+    }
+    canonical.push_str(url.path().trim_end_matches('/'));
+
+    let params: Vec<String> = url.query_pairs()
+        .filter(|(key, _)| !is_tracking_param(key))
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+
+    if !params.is_empty() {
+        canonical.push('?');
+        canonical.push_str(&params.join("&"));
+    }
+
+    canonical
+}
+
+/// Collapses posts from different feeds that share an id, a link or their
+/// content into one post that lists all of their sources. The copy that comes
+/// first is the one shown. Posts from the same feed are never collapsed, since
+/// some feeds give every post the same link, like the site's home page.
+fn deduplicated<'posts>(
+    posts: impl Iterator<Item = (&'posts Post, Source<'posts>)>,
+    now: Timestamp,
+) -> Vec<PostHolder<'posts>> {
+    let mut holders: Vec<PostHolder<'posts>> = Vec::new();
+    let mut seen: HashMap<DuplicateKey<'posts>, usize> = HashMap::new();
+
+    for (post, source) in posts {
+        let keys = duplicate_keys(post);
+
+        // If the keys match more than one post, we only add to the first. That
+        // seems rare enough to not be worth merging the others as well.
+        let duplicate_of = keys.iter()
+            .filter_map(|key| seen.get(key).copied())
+            .find(|&index| !holders[index].sources.contains(&source));
+
+        let index = match duplicate_of {
+            Some(index) => {
+                holders[index].sources.push(source);
+                index
+            },
+            None => {
+                holders.push(PostHolder { post, sources: vec![source], now });
+                holders.len() - 1
+            },
+        };
+
+        for key in keys {
+            seen.entry(key).or_insert(index);
+        }
     }
+
+    holders
+}
+
+/// Newest first, with undated posts after all the dated ones, and otherwise in
+/// the order the feeds listed them.
+fn newest_first(mut posts: Vec<PostHolder<'_>>) -> PostHolderIter<'_> {
+    posts.sort_by_key(|holder| core::cmp::Reverse(holder.post.date()));
+
+    posts.into_iter()
 }
 
 type PostHolderIter<'holder> = std::vec::IntoIter<PostHolder<'holder>>;
@@ -921,6 +2937,8 @@ type Section<'holder> = render::Section<
 >;
 
 impl <'posts> render::Data<'_> for Data<'_, 'posts> {
+    type GoneFeed = &'posts Url;
+    type GoneFeeds = std::vec::IntoIter<&'posts Url>;
     type PostHolder = PostHolder<'posts>;
     type Posts = PostHolderIter<'posts>;
     type RefreshTimestamps = std::array::IntoIter<
@@ -931,61 +2949,90 @@ impl <'posts> render::Data<'_> for Data<'_, 'posts> {
         Section<'posts>,
         2
     >;
-    type Timestamp = Timestamp;
+    type Timestamp = Time;
 
     fn post_sections(&self) -> Self::Sections {
         [
             render::Section {
                 kind: render::SectionKind::Local,
-                posts: self.local_posts.iter()
+                posts: newest_first(deduplicated(self.local_posts.iter()
                 .flat_map(|(path, posts): (&LocalFeedPath, &Posts)| {
                     posts.posts.iter()
-                        .map(|post| PostHolder{
-                            post,
-                            source: Source::LocalFeedPath(path),
-                        })
-                }).collect::<Vec<_>>().into_iter(),
+                        .map(|post| (post, Source::LocalFeedPath(path)))
+                }), self.now)),
             },
            render::Section {
                 kind: render::SectionKind::Remote,
-                posts: self.remote_posts.iter()
+                posts: newest_first(deduplicated(self.remote_posts.iter()
+                .filter(|(o_url, _)| !self.gone_feeds.contains(&o_url.url))
                 .flat_map(|(o_url, posts): (&OrderedUrl, &Posts)|
                     posts.posts.iter()
-                        .map(|post| PostHolder{
-                            post,
-                            source: Source::Url(&o_url.url)
-                        })
-                ).collect::<Vec<_>>().into_iter()
+                        .map(|post| (post, Source::Url(&o_url.url)))
+                ), self.now))
             }
         ].into_iter()
     }
 
     fn refresh_timestamps(&self) -> Self::RefreshTimestamps {
+        // Anything that has not been fetched yet has the default timestamp, and
+        // the oldest one is how fresh everything is.
+        let oldest = |fetched_ats: &mut dyn Iterator<Item = Timestamp>| {
+            fetched_ats.min()
+                .filter(|&at| at != Timestamp::DEFAULT)
+                .map(|at| Time { at, now: self.now })
+        };
+
         [
             render::RefreshTimestamp {
                 kind: render::RefreshKind::Local,
-                timestamp: self.local_posts.values()
-                    .fold(Timestamp::MAX, |acc, post|
-                        if acc > post.fetched_at {
-                            post.fetched_at
-                        } else {
-                            acc
-                        }
-                    ),
+                timestamp: oldest(
+                    &mut self.local_posts.values().map(|posts| posts.fetched_at)
+                ),
             },
             render::RefreshTimestamp {
                 kind: render::RefreshKind::Remote,
-                timestamp: self.remote_posts.values()
-                .fold(Timestamp::MAX, |acc, post|
-                    if acc > post.fetched_at {
-                        post.fetched_at
-                    } else {
-                        acc
-                    }
+                timestamp: oldest(
+                    &mut self.remote_posts.iter()
+                        .filter(|(o_url, _)| !self.gone_feeds.contains(&o_url.url))
+                        .map(|(_, posts)| posts.fetched_at)
                 ),
             },
         ].into_iter()
     }
+
+    fn gone_feeds(&self) -> Self::GoneFeeds {
+        // Gone feeds that are no longer in the remote feeds file have already
+        // been dealt with.
+        self.remote_posts.keys()
+            .map(|o_url| &o_url.url)
+            .filter(|url| self.gone_feeds.contains(url))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+/// A time to be shown, along with what to show it relative to.
+struct Time {
+    at: Timestamp,
+    now: Timestamp,
+}
+
+impl render::Time for Time {
+    type Relative = timestamp::Relative;
+    type ShortDate = timestamp::ShortDate;
+    type Full = Timestamp;
+
+    fn relative(&self) -> Self::Relative {
+        self.at.relative_to(self.now)
+    }
+
+    fn short_date(&self) -> Self::ShortDate {
+        self.at.short_date()
+    }
+
+    fn full(&self) -> Self::Full {
+        self.at
+    }
 }
 
 #[derive(PartialEq, Eq)]
@@ -1001,7 +3048,7 @@ impl <'path> render::Target for Target<'path, '_> {
     fn label(&self) -> Self::Label {
         self.path
             .strip_prefix(&self.root.0)
-            .unwrap_or(&self.path)
+            .unwrap_or(self.path)
             .display()
     }
 
@@ -1027,8 +3074,20 @@ impl core::fmt::Display for RemoteFeedUrlsError {
 
 impl std::error::Error for RemoteFeedUrlsError {}
 
+/// Absolute paths are accepted as well as URLs, as a shorter way to write
+/// `file:` URLs.
+fn parse_remote_feed_url(s: &str) -> Result<Url, fetch::UrlParseError> {
+    Url::parse(s).or_else(|e| {
+        if Path::new(s).is_absolute() {
+            Url::from_file_path(s).map_err(|()| e)
+        } else {
+            Err(e)
+        }
+    })
+}
+
 fn load_remote_feed_urls(
-    remote_feeds_file: &mut File,
+    remote_feeds_file: &mut impl std::io::Read,
     remote_posts: &mut RemotePosts,
 ) -> Result<(), RemoteFeedUrlsError> {
     use RemoteFeedUrlsError as E;
@@ -1040,7 +3099,14 @@ fn load_remote_feed_urls(
     ).map_err(E::Io)?;
 
     for (i, line) in remote_feeds_string.lines().enumerate() {
-        let url = Url::parse(line)
+        let line = line.trim();
+
+        // Blank lines are easy to leave behind when editing by hand.
+        if line.is_empty() {
+            continue
+        }
+
+        let url = parse_remote_feed_url(line)
             .map_err(E::UrlParse)?;
 
         remote_posts.entry(
@@ -1060,4 +3126,616 @@ fn load_remote_feed_urls(
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Replaces `from` with `to` in the remote feeds file, keeping its place in the
+/// order, then reloads `remote_posts` from the file. If `to` is already in the
+/// file, `from` is just removed.
+fn move_remote_feed(
+    remote_posts: &mut RemotePosts,
+    from: &Url,
+    to: &Url,
+    root: &Root,
+    backups: &write_atomically::Backups,
+) -> Result<(), RemoteFeedUrlsError> {
+    use std::io::Write;
+    use RemoteFeedUrlsError as E;
+
+    // Whatever a server redirects to gets written into the remote feeds file,
+    // where an `exec:` URL would be run on the next refresh.
+    if !is_web_url(from) || !is_web_url(to) {
+        return Err(E::Io(std::io::Error::other(format!(
+            "Will not move {from} to {to}, as only http and https feeds can be moved"
+        ))))
+    }
+
+    let path = root.path_to(REMOTE_FEEDS);
+
+    let remote_feeds_string = std::fs::read_to_string(&path)
+        .map_err(E::Io)?;
+
+    let is = |line: &str, url: &Url| parse_remote_feed_url(line).ok().as_ref() == Some(url);
+
+    let already_listed = remote_feeds_string.lines().any(|line| is(line, to));
+
+    // If the file was edited in the meantime, the move will be tried again the
+    // next time the feed is fetched.
+    write_atomically::write_atomically_if_unchanged(
+        &path,
+        remote_feeds_string.as_bytes(),
+        backups,
+        |file| {
+            for line in remote_feeds_string.lines() {
+                if !is(line, from) {
+                    writeln!(file, "{line}")?;
+                } else if !already_listed {
+                    writeln!(file, "{to}")?;
+                }
+            }
+
+            Ok(())
+        }
+    ).map_err(|e: write_atomically::Error<write_atomically::IfUnchangedError<std::io::Error>>|
+        E::Io(std::io::Error::other(e))
+    )?;
+
+    if !already_listed {
+        let moved = remote_posts.keys()
+            .find(|o_url| o_url.url == *from)
+            .cloned();
+
+        if let Some(o_url) = moved {
+            if let Some(posts) = remote_posts.remove(&o_url) {
+                remote_posts.insert(
+                    OrderedUrl { url: to.clone(), order_key: o_url.order_key },
+                    posts,
+                );
+            }
+        }
+    }
+
+    reload_remote_feed_urls(remote_posts, root)
+}
+
+/// Re-reads the remote feeds file, unlike `load_remote_feed_urls`, dropping any
+/// feeds that are no longer listed. The posts of the feeds that still are, are
+/// kept. A missing file is treated like an empty one.
+fn reload_remote_feed_urls(
+    remote_posts: &mut RemotePosts,
+    root: &Root,
+) -> Result<(), RemoteFeedUrlsError> {
+    use RemoteFeedUrlsError as E;
+
+    let remote_feeds = match std::fs::read(root.path_to(REMOTE_FEEDS)) {
+        Ok(remote_feeds) => remote_feeds,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(E::Io(e)),
+    };
+
+    let mut reloaded = RemotePosts::new();
+
+    load_remote_feed_urls(
+        &mut remote_feeds.as_slice(),
+        &mut reloaded,
+    )?;
+
+    let mut previous: HashMap<Url, Posts> = std::mem::take(remote_posts)
+        .into_iter()
+        .map(|(o_url, posts)| (o_url.url, posts))
+        .collect();
+
+    for (o_url, posts) in reloaded.iter_mut() {
+        if let Some(previous_posts) = previous.remove(&o_url.url) {
+            *posts = previous_posts;
+        }
+    }
+
+    *remote_posts = reloaded;
+
+    Ok(())
+}
+
+/// A missing gone feeds file is treated like an empty one.
+fn load_gone_feeds(root: &Root) -> Result<BTreeSet<Url>, RemoteFeedUrlsError> {
+    use RemoteFeedUrlsError as E;
+
+    let gone_feeds_string = match std::fs::read_to_string(root.path_to(GONE_FEEDS)) {
+        Ok(string) => string,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(E::Io(e)),
+    };
+
+    // Like `remote-feeds`, this is meant to be edited by hand.
+    gone_feeds_string.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| parse_remote_feed_url(line).map_err(E::UrlParse))
+        .collect()
+}
+
+fn mark_feed_gone(
+    gone_feeds: &mut BTreeSet<Url>,
+    url: &Url,
+    root: &Root,
+    backups: &write_atomically::Backups,
+) -> Result<(), write_atomically::Error<std::io::Error>> {
+    use std::io::Write;
+
+    if gone_feeds.contains(url) {
+        return Ok(())
+    }
+
+    let mut updated = gone_feeds.clone();
+    updated.insert(url.clone());
+
+    write_atomically::write_atomically(
+        root.path_to(GONE_FEEDS),
+        backups,
+        |file| {
+            for url in &updated {
+                writeln!(file, "{url}")?;
+            }
+
+            Ok(())
+        }
+    )?;
+
+    *gone_feeds = updated;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(link: &str, content: &str) -> Post {
+        Post {
+            id: None,
+            title: None,
+            summary: None,
+            content: Some(content.to_owned()),
+            links: vec![link.to_owned()],
+            published: None,
+            updated: None,
+        }
+    }
+
+    fn url(s: &str) -> Url {
+        Url::parse(s).unwrap()
+    }
+
+    fn rss(title: &str) -> fetch::Fixture {
+        fetch::Fixture::Body {
+            content_type: Some("application/rss+xml".to_owned()),
+            body: format!(
+                "<?xml version='1.0'?>\
+                <rss version='2.0'><channel>\
+                    <title>Feed</title><link>https://example.com</link>\
+                    <description>A feed</description>\
+                    <item><title>{title}</title></item>\
+                </channel></rss>"
+            ).into_bytes(),
+        }
+    }
+
+    /// A data directory with `remote_feeds` as its list of remote feeds, opened
+    /// with `fixtures` in place of the network.
+    fn state_with(
+        remote_feeds: &str,
+        fixtures: fetch::Fixtures,
+    ) -> (tempfile::TempDir, State) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(REMOTE_FEEDS), remote_feeds).unwrap();
+
+        let state = State::open(dir.path().to_owned())
+            .unwrap()
+            .with_fetcher(fixtures);
+
+        (dir, state)
+    }
+
+    fn remote_titles(state: &State) -> Vec<(String, Option<String>)> {
+        state.snapshot().remote_posts.iter()
+            .flat_map(|(o_url, posts)| posts.posts.iter().map(|post| {
+                (o_url.url.to_string(), post.title.clone())
+            }))
+            .collect()
+    }
+
+    #[test]
+    fn refreshing_fetches_every_remote_feed() {
+        let mut fixtures = fetch::Fixtures::new();
+        fixtures.insert(url("https://a.example/feed"), rss("From A"))
+            .insert(url("https://b.example/feed"), rss("From B"));
+
+        let (_dir, state) = state_with(
+            "https://a.example/feed\nhttps://b.example/feed\n",
+            fixtures,
+        );
+
+        state.fetch_remote_posts().unwrap();
+
+        assert_eq!(
+            remote_titles(&state),
+            [
+                ("https://a.example/feed".to_owned(), Some("From A".to_owned())),
+                ("https://b.example/feed".to_owned(), Some("From B".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn permanent_redirects_rewrite_remote_feeds() {
+        let mut fixtures = fetch::Fixtures::new();
+        fixtures.insert(
+            url("https://old.example/feed"),
+            fetch::Fixture::Redirect {
+                status: 301,
+                location: "https://new.example/feed".to_owned(),
+            },
+        ).insert(url("https://new.example/feed"), rss("Moved"))
+            .insert(url("https://stays.example/feed"), rss("Stayed"));
+
+        let (dir, state) = state_with(
+            "https://old.example/feed\nhttps://stays.example/feed\n",
+            fixtures,
+        );
+
+        state.fetch_remote_posts().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(REMOTE_FEEDS)).unwrap(),
+            "https://new.example/feed\nhttps://stays.example/feed\n",
+        );
+        assert_eq!(
+            remote_titles(&state),
+            [
+                ("https://new.example/feed".to_owned(), Some("Moved".to_owned())),
+                ("https://stays.example/feed".to_owned(), Some("Stayed".to_owned())),
+            ]
+        );
+    }
+
+    #[test]
+    fn redirects_to_commands_or_files_are_not_saved() {
+        let remote_feeds = "https://exec.example/feed\nhttps://file.example/feed\n";
+
+        let mut fixtures = fetch::Fixtures::new();
+        fixtures.insert(
+            url("https://exec.example/feed"),
+            fetch::Fixture::Redirect {
+                status: 301,
+                location: "exec:touch pwned".to_owned(),
+            },
+        ).insert(
+            url("https://file.example/feed"),
+            fetch::Fixture::Redirect {
+                status: 308,
+                location: "file:///etc/passwd".to_owned(),
+            },
+        );
+
+        let (dir, state) = state_with(remote_feeds, fixtures);
+
+        let _ = state.fetch_remote_posts();
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(REMOTE_FEEDS)).unwrap(),
+            remote_feeds,
+        );
+        assert!(!dir.path().join("pwned").exists());
+    }
+
+    #[test]
+    fn refreshing_never_adds_or_rewrites_commands_or_local_files() {
+        let remote_feeds = "\
+            exec:true\n\
+            file:///moved/feed.xml\n\
+            file:///gone/feed.xml\n\
+            https://a.example/feed\n";
+
+        let mut fixtures = fetch::Fixtures::new();
+        fixtures.insert(
+            url("file:///moved/feed.xml"),
+            fetch::Fixture::Redirect {
+                status: 301,
+                location: "https://elsewhere.example/feed".to_owned(),
+            },
+        ).insert(url("https://elsewhere.example/feed"), rss("Elsewhere"))
+            .insert(url("file:///gone/feed.xml"), fetch::Fixture::Status(410))
+            .insert(
+                url("https://a.example/feed"),
+                fetch::Fixture::Redirect {
+                    status: 301,
+                    location: "exec:true".to_owned(),
+                },
+            );
+
+        let (dir, state) = state_with(remote_feeds, fixtures);
+
+        let _ = state.fetch_remote_posts();
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(REMOTE_FEEDS)).unwrap(),
+            remote_feeds,
+        );
+        assert!(!dir.path().join(GONE_FEEDS).exists());
+        assert!(state.snapshot().gone_feeds.is_empty());
+    }
+
+    #[test]
+    fn showing_the_restore_page_leaves_local_posts_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let fetch::Fixture::Body { body, .. } = rss("Local") else {
+            unreachable!()
+        };
+        std::fs::create_dir(dir.path().join(LOCAL_FEEDS)).unwrap();
+        std::fs::write(dir.path().join(LOCAL_FEEDS).join("feed.xml"), body).unwrap();
+
+        let state = Arc::new(State::open(dir.path().to_owned()).unwrap());
+        let local_titles = |state: &State| -> Vec<Option<String>> {
+            state.snapshot().local_posts.values()
+                .flat_map(|posts| posts.posts.iter().map(|post| post.title.clone()))
+                .collect()
+        };
+        assert_eq!(local_titles(&state), [Some("Local".to_owned())]);
+
+        state.perform(Task::ShowRestorePage, "token").unwrap();
+        state.perform(Task::ShowLocalAddForm, "token").unwrap();
+
+        assert_eq!(local_titles(&state), [Some("Local".to_owned())]);
+    }
+
+    #[test]
+    fn read_only_refreshes_save_nothing() {
+        let remote_feeds = "https://old.example/feed\nhttps://gone.example/feed\n";
+
+        let mut fixtures = fetch::Fixtures::new();
+        fixtures.insert(
+            url("https://old.example/feed"),
+            fetch::Fixture::Redirect {
+                status: 301,
+                location: "https://new.example/feed".to_owned(),
+            },
+        ).insert(url("https://new.example/feed"), rss("Moved"))
+            .insert(url("https://gone.example/feed"), fetch::Fixture::Status(410));
+
+        let (dir, state) = state_with(remote_feeds, fixtures);
+
+        let Task::Refresh(flags) = Task::Refresh(REFRESH_REMOTE).read_only() else {
+            unreachable!()
+        };
+        assert!(!Task::Refresh(flags).writes());
+
+        let _ = state.refresh_by_flags(flags);
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(REMOTE_FEEDS)).unwrap(),
+            remote_feeds,
+        );
+        assert!(!dir.path().join(GONE_FEEDS).exists());
+        assert!(!dir.path().join(BACKUPS).exists());
+        assert_eq!(
+            remote_titles(&state),
+            [("https://old.example/feed".to_owned(), Some("Moved".to_owned()))],
+        );
+    }
+
+    #[test]
+    fn failed_updates_are_not_published() {
+        let (_dir, state) = state_with("", fetch::Fixtures::new());
+        let events = state.subscribe();
+        let post = post("https://example.com/post", "content");
+
+        let result: Result<(), _> = state.update(|snapshot| {
+            snapshot.remote_posts.insert(
+                OrderedUrl { url: url("https://a.example/feed"), order_key: 0 },
+                Posts { posts: vec![post.clone()], fetched_at: Timestamp::DEFAULT },
+            );
+
+            Err("failed")
+        });
+
+        assert_eq!(result, Err("failed"));
+        assert!(state.snapshot().remote_posts.is_empty());
+        assert!(events.0.try_recv().is_err());
+
+        state.update(|snapshot| {
+            snapshot.remote_posts.insert(
+                OrderedUrl { url: url("https://a.example/feed"), order_key: 0 },
+                Posts { posts: vec![post], fetched_at: Timestamp::DEFAULT },
+            );
+
+            Ok::<_, ()>(())
+        }).unwrap();
+
+        assert_eq!(state.snapshot().remote_posts.len(), 1);
+        assert!(matches!(
+            events.0.try_recv(),
+            Ok(Event::NewPosts(ids)) if ids.len() == 1
+        ));
+    }
+
+    #[test]
+    fn blank_lines_in_hand_edited_files_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(REMOTE_FEEDS),
+            "https://a.example/feed\n\n  \n https://b.example/feed\n\n",
+        ).unwrap();
+        std::fs::write(
+            dir.path().join(GONE_FEEDS),
+            "\nhttps://gone.example/feed  \n   \n",
+        ).unwrap();
+
+        let state = State::open(dir.path().to_owned()).unwrap();
+        let snapshot = state.snapshot();
+
+        let urls: Vec<String> = snapshot.remote_posts.keys()
+            .map(|o_url| o_url.url.to_string())
+            .collect();
+        assert_eq!(urls, ["https://a.example/feed", "https://b.example/feed"]);
+        assert_eq!(
+            snapshot.gone_feeds,
+            BTreeSet::from([url("https://gone.example/feed")]),
+        );
+    }
+
+    #[test]
+    fn gone_feeds_are_recorded_and_no_longer_shown() {
+        let mut fixtures = fetch::Fixtures::new();
+        fixtures.insert(url("https://gone.example/feed"), fetch::Fixture::Status(410))
+            .insert(url("https://here.example/feed"), rss("Here"));
+
+        let (dir, state) = state_with(
+            "https://gone.example/feed\nhttps://here.example/feed\n",
+            fixtures,
+        );
+
+        // A feed being gone is not a failure to fetch it.
+        state.fetch_remote_posts().unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(GONE_FEEDS)).unwrap(),
+            "https://gone.example/feed\n",
+        );
+        assert!(
+            state.snapshot().gone_feeds.contains(&url("https://gone.example/feed"))
+        );
+
+        use render::Data as _;
+        let snapshot = state.snapshot();
+        let shown: Vec<String> = Data {
+            root: &state.root,
+            local_posts: &snapshot.local_posts,
+            remote_posts: &snapshot.remote_posts,
+            gone_feeds: &snapshot.gone_feeds,
+            now: Timestamp::DEFAULT,
+        }.post_sections()
+            .flat_map(|section| section.posts)
+            .flat_map(|holder| holder.sources)
+            .map(|source| source.to_string())
+            .collect();
+
+        assert_eq!(shown, ["https://here.example/feed"]);
+    }
+
+    #[test]
+    fn canonical_link_ignores_what_does_not_change_the_page() {
+        let expected = canonical_link("https://example.com/post?id=3");
+
+        for link in [
+            "http://example.com/post?id=3",
+            "https://EXAMPLE.com/post?id=3",
+            "https://example.com/post/?id=3",
+            "https://example.com/post?id=3#comments",
+            "https://example.com/post?utm_source=feed&id=3&utm_medium=rss",
+            "https://example.com/post?fbclid=abc&id=3",
+            "  https://example.com/post?id=3\n",
+            "https://example.com:443/post?id=3",
+        ] {
+            assert_eq!(canonical_link(link), expected, "{link}");
+        }
+    }
+
+    #[test]
+    fn canonical_link_keeps_what_changes_the_page() {
+        let base = canonical_link("https://example.com/post?id=3");
+
+        for link in [
+            "https://example.com/post?id=4",
+            "https://example.com/post",
+            "https://example.com/other?id=3",
+            "https://example.org/post?id=3",
+            "https://example.com:8080/post?id=3",
+        ] {
+            assert_ne!(canonical_link(link), base, "{link}");
+        }
+    }
+
+    #[test]
+    fn canonical_link_leaves_unparseable_links_alone() {
+        assert_eq!(canonical_link(" not a url "), "not a url");
+    }
+
+    #[test]
+    fn deduplicated_merges_copies_from_different_feeds() {
+        let a = url("https://a.example");
+        let b = url("https://b.example");
+        let c = url("https://c.example");
+        let by_link = post("https://example.com/post?utm_source=a", "first");
+        let by_link_copy = post("http://example.com/post/", "different words");
+        let by_content_copy = post("https://elsewhere.example/", "first");
+
+        let holders = deduplicated(
+            [
+                (&by_link, Source::Url(&a)),
+                (&by_link_copy, Source::Url(&b)),
+                (&by_content_copy, Source::Url(&c)),
+            ].into_iter(),
+            Timestamp::DEFAULT,
+        );
+
+        assert_eq!(holders.len(), 1);
+        assert!(std::ptr::eq(holders[0].post, &by_link));
+        assert!(
+            holders[0].sources
+                == [Source::Url(&a), Source::Url(&b), Source::Url(&c)]
+        );
+    }
+
+    #[test]
+    fn deduplicated_keeps_posts_from_the_same_feed() {
+        let (a, b) = (url("https://a.example"), url("https://b.example"));
+        // Like a feed that links every post to the same dashboard.
+        let posts = [
+            post("https://ci.example/", "build 1 passed"),
+            post("https://ci.example/", "build 2 failed"),
+            post("https://ci.example/", "build 3 passed"),
+        ];
+        let copy = post("https://ci.example/", "build 2 failed");
+
+        let holders = deduplicated(
+            posts.iter()
+                .map(|post| (post, Source::Url(&a)))
+                .chain([(&copy, Source::Url(&b))]),
+            Timestamp::DEFAULT,
+        );
+
+        assert_eq!(holders.len(), 3);
+        assert!(holders.iter().all(|holder| holder.sources[0] == Source::Url(&a)));
+        // The copy has the same link as all three, so it goes with the first.
+        assert!(holders[0].sources == [Source::Url(&a), Source::Url(&b)]);
+    }
+
+    #[test]
+    fn deduplicated_only_trusts_ids_that_look_like_uris() {
+        let (a, b) = (url("https://a.example"), url("https://b.example"));
+        let mut numbered = post("https://a.example/1", "one");
+        numbered.id = Some("1".to_owned());
+        let mut other_numbered = post("https://b.example/1", "uno");
+        other_numbered.id = Some("1".to_owned());
+
+        let holders = deduplicated(
+            [
+                (&numbered, Source::Url(&a)),
+                (&other_numbered, Source::Url(&b)),
+            ].into_iter(),
+            Timestamp::DEFAULT,
+        );
+        assert_eq!(holders.len(), 2);
+
+        numbered.id = Some("tag:example.com,2026:1".to_owned());
+        other_numbered.id = numbered.id.clone();
+
+        let holders = deduplicated(
+            [
+                (&numbered, Source::Url(&a)),
+                (&other_numbered, Source::Url(&b)),
+            ].into_iter(),
+            Timestamp::DEFAULT,
+        );
+        assert_eq!(holders.len(), 1);
+    }
+}
@@ -12,6 +12,337 @@ pub type Result<A = ()> = core::result::Result<A, Error>;
 /// A way to incrementally output HTML.
 pub trait Output: core::fmt::Write {}
 
+#[derive(Clone, Copy)]
+pub enum Theme {
+    /// Whichever of the others the browser says the user prefers.
+    Auto,
+    Dark,
+    Light,
+}
+
+#[derive(Clone, Copy)]
+pub enum TimeFormat {
+    Relative,
+    ShortDate,
+    Full,
+}
+
+/// The ways a time can be shown.
+pub trait Time
+where
+    Self::Relative: Display,
+    Self::ShortDate: Display,
+    Self::Full: Display,
+{
+    type Relative;
+    type ShortDate;
+    type Full;
+
+    /// Like "3 hours ago".
+    fn relative(&self) -> Self::Relative;
+
+    fn short_date(&self) -> Self::ShortDate;
+
+    fn full(&self) -> Self::Full;
+}
+
+/// Shows a time in the preferred format, with the full time in a tooltip.
+struct ShownTime<'time, T>(&'time T, TimeFormat);
+
+impl <T: Time> Display for ShownTime<'_, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let Self(time, format) = self;
+
+        write!(f, "<time title='{}'>", time.full())?;
+
+        match format {
+            TimeFormat::Relative => write!(f, "{}", time.relative())?,
+            TimeFormat::ShortDate => write!(f, "{}", time.short_date())?,
+            TimeFormat::Full => write!(f, "{}", time.full())?,
+        }
+
+        write!(f, "</time>")
+    }
+}
+
+/// Things that affect how pages are displayed, mostly from user settings.
+#[derive(Clone, Copy)]
+pub struct Preferences {
+    pub theme: Theme,
+    pub expand_sections: bool,
+    /// Should change whenever the content served at `page_names::STYLESHEET`
+    /// does, so that the stylesheet can be cached indefinitely.
+    pub stylesheet_version: u64,
+    pub keyboard_shortcuts: bool,
+    /// Like `stylesheet_version`, but for `page_names::KEYBOARD_SCRIPT`.
+    pub keyboard_script_version: u64,
+    pub live_updates: bool,
+    /// Like `stylesheet_version`, but for `page_names::LIVE_SCRIPT`.
+    pub live_updates_script_version: u64,
+    pub time_format: TimeFormat,
+    /// Whether users log in, and so need a way to log out.
+    pub logins: bool,
+}
+
+/// The built-in styles. Served at `page_names::STYLESHEET`, along with any user
+/// styles, which come after these, so they can override them.
+pub const BUILT_IN_STYLES: &str = "\
+:root {
+    color-scheme: dark;
+    --foreground: #eee;
+    --background: #222;
+}
+
+:root[data-theme=light] {
+    color-scheme: light;
+    --foreground: #111;
+    --background: #fafafa;
+}
+
+@media (prefers-color-scheme: light) {
+    :root[data-theme=auto] {
+        color-scheme: light;
+        --foreground: #111;
+        --background: #fafafa;
+    }
+}
+
+* {
+    color: var(--foreground);
+    background-color: var(--background);
+}
+
+form.fields { display: table; }
+form.fields p { display: table-row; }
+form.fields label { display: table-cell; text-align: right; }
+form.fields input, form.fields select { display: table-cell; }
+
+.post.selected { outline: 1px solid var(--foreground); }
+.post.read { opacity: 0.6; }
+.post.saved h2::before { content: '\\2605  '; }
+
+.refreshed { margin-left: 0.5em; opacity: 0.6; }
+
+#new-posts button { width: 100%; }
+#feed-errors { color: #d44; }
+#feed-errors[hidden], #new-posts[hidden] { display: none; }
+#gone-feeds { border: 1px solid #d44; padding: 0 1em; }
+
+.refresh-progress td { padding-right: 1em; }
+.refresh-progress .pending, .refresh-progress .done,
+.refresh-progress .backing-off { opacity: 0.6; }
+.refresh-progress .failed td:last-child { color: #d44; }
+
+#keyboard-help {
+    position: fixed;
+    top: 1em;
+    right: 1em;
+    padding: 0 1em;
+    border: 1px solid var(--foreground);
+}
+#keyboard-help[hidden] { display: none; }
+#keyboard-help dt { float: left; clear: left; width: 2em; font-weight: bold; }
+";
+
+/// Served at `page_names::KEYBOARD_SCRIPT`. Relies on the ids and data attributes
+/// that `feeds` puts on each post. Everything it does is optional; the pages work
+/// the same without it. The read and saved marks are kept in the browser's local
+/// storage, keyed by post id.
+pub const KEYBOARD_SHORTCUTS_SCRIPT: &str = r##"(function () {
+"use strict";
+
+function findPosts() {
+    return Array.prototype.slice.call(document.querySelectorAll("article.post"));
+}
+
+var posts = findPosts();
+var help = document.getElementById("keyboard-help");
+var current = -1;
+
+function load(key) {
+    try {
+        return JSON.parse(localStorage.getItem(key)) || {};
+    } catch (e) {
+        return {};
+    }
+}
+
+function store(key, marks) {
+    try {
+        localStorage.setItem(key, JSON.stringify(marks));
+    } catch (e) {
+        // Storage may be disabled or full; the marks just won't persist.
+    }
+}
+
+var marks = { "rrr-read": load("rrr-read"), "rrr-saved": load("rrr-saved") };
+
+function show(post) {
+    post.classList.toggle("read", !!marks["rrr-read"][post.id]);
+    post.classList.toggle("saved", !!marks["rrr-saved"][post.id]);
+}
+
+posts.forEach(show);
+
+// Sent by the live updates script, after it replaces the posts.
+document.addEventListener("rrr:posts-replaced", function () {
+    posts = findPosts();
+    current = -1;
+    posts.forEach(show);
+});
+
+function select(i) {
+    if (i < 0 || i >= posts.length) {
+        return;
+    }
+    if (current >= 0) {
+        posts[current].classList.remove("selected");
+    }
+    current = i;
+
+    var post = posts[i];
+    var section = post.closest("details");
+    if (section) {
+        section.open = true;
+    }
+    post.classList.add("selected");
+    post.scrollIntoView({ block: "start" });
+    history.replaceState(null, "", "#" + post.id);
+}
+
+function mark(key, value) {
+    var post = posts[current];
+    if (!post) {
+        return;
+    }
+    if (value === undefined) {
+        value = !marks[key][post.id];
+    }
+    if (value) {
+        marks[key][post.id] = Date.now();
+    } else {
+        delete marks[key][post.id];
+    }
+    store(key, marks[key]);
+    show(post);
+}
+
+if (location.hash) {
+    select(posts.findIndex(function (post) {
+        return "#" + post.id === location.hash;
+    }));
+}
+
+document.addEventListener("keydown", function (event) {
+    var target = event.target;
+    if (
+        event.ctrlKey || event.metaKey || event.altKey
+        || target.isContentEditable
+        || /^(INPUT|TEXTAREA|SELECT)$/.test(target.tagName)
+    ) {
+        return;
+    }
+
+    switch (event.key) {
+        case "j": select(current + 1); break;
+        case "k": select(current < 0 ? 0 : current - 1); break;
+        case "o": {
+            var post = posts[current];
+            if (post && post.dataset.link) {
+                window.open(post.dataset.link, "_blank", "noopener");
+                mark("rrr-read", true);
+            }
+        } break;
+        case "m": mark("rrr-read"); break;
+        case "s": mark("rrr-saved"); break;
+        case "?": help.hidden = !help.hidden; break;
+        case "Escape": help.hidden = true; break;
+        default: return;
+    }
+
+    event.preventDefault();
+});
+})();
+"##;
+
+/// Served at `page_names::LIVE_SCRIPT`. Listens to `page_names::EVENTS`,
+/// and when there are posts that are not on the page yet, offers to replace the
+/// posts with the ones from `page_names::POSTS`. Relies on the elements that
+/// `home_page` adds when `live_updates` is set.
+pub const LIVE_UPDATES_SCRIPT: &str = r##"(function () {
+"use strict";
+
+var bar = document.getElementById("new-posts");
+var button = bar.querySelector("button");
+var errors = document.getElementById("feed-errors");
+var feeds = document.getElementById("feeds");
+var pending = {};
+
+if (!window.EventSource) {
+    return;
+}
+
+var events = new EventSource("/events");
+
+events.addEventListener("posts", function (event) {
+    event.data.split(" ").forEach(function (id) {
+        if (id && !document.getElementById(id)) {
+            pending[id] = true;
+        }
+    });
+
+    var count = Object.keys(pending).length;
+    if (count) {
+        button.textContent = count + (count === 1 ? " new post" : " new posts");
+        bar.hidden = false;
+    }
+});
+
+events.addEventListener("feed-error", function (event) {
+    var item = document.createElement("li");
+    item.textContent = event.data;
+    errors.appendChild(item);
+    errors.hidden = false;
+});
+
+button.addEventListener("click", function () {
+    fetch("/posts", { credentials: "same-origin" })
+        .then(function (response) {
+            if (!response.ok) {
+                throw new Error(response.statusText);
+            }
+            return response.text();
+        })
+        .then(function (html) {
+            // Keep the sections the user opened, open.
+            var open = Array.prototype.map.call(
+                feeds.querySelectorAll("details"),
+                function (section) { return section.open; }
+            );
+
+            feeds.innerHTML = html;
+
+            Array.prototype.forEach.call(
+                feeds.querySelectorAll("details"),
+                function (section, i) {
+                    if (open[i] !== undefined) {
+                        section.open = open[i];
+                    }
+                }
+            );
+
+            pending = {};
+            bar.hidden = true;
+            document.dispatchEvent(new CustomEvent("rrr:posts-replaced"));
+        })
+        .catch(function () {
+            // Reloading the whole page will show them, or the actual problem.
+            location.reload();
+        });
+});
+})();
+"##;
+
 pub enum RefreshKind {
     Local,
     Remote,
@@ -19,7 +350,8 @@ pub enum RefreshKind {
 
 pub struct RefreshTimestamp<Timestamp> {
     pub kind: RefreshKind,
-    pub timestamp: Timestamp,
+    /// `None` if nothing has been refreshed yet.
+    pub timestamp: Option<Timestamp>,
 }
 
 pub enum SectionKind {
@@ -45,15 +377,29 @@ pub struct Post<'post, Link> {
 /// A way to access a `Post` which may or may not ultimately own it.
 pub trait PostHolder
 where
+    Self::Id: Display,
     Self::Link: AsRef<str>,
     Self::Source: Display,
+    Self::Sources: Iterator<Item = Self::Source>,
+    Self::Time: Time,
 {
+    type Id;
     type Link;
     type Source;
+    type Sources;
+    type Time;
+
+    /// Should be the same for the same post across refreshes and restarts, and be
+    /// usable as an HTML id.
+    fn id(&self) -> Self::Id;
 
     fn get_post(&self) -> Post<'_, Self::Link>;
 
-    fn source(&self) -> Self::Source;
+    /// Every feed the post showed up in. Should not be empty.
+    fn sources(&self) -> Self::Sources;
+
+    /// When the post was published, if the feed said.
+    fn time(&self) -> Option<Self::Time>;
 }
 
 pub trait RootDisplay
@@ -68,12 +414,16 @@ where
 /// A way to access the data we need for rendering.
 pub trait Data<'posts>: RootDisplay
 where
+    Self::GoneFeed: Display,
+    Self::GoneFeeds: Iterator<Item = Self::GoneFeed>,
     Self::PostHolder: PostHolder,
     Self::Posts: Iterator<Item = Self::PostHolder>,
     Self::RefreshTimestamps: Iterator<Item = RefreshTimestamp<Self::Timestamp>>,
     Self::Sections: Iterator<Item = Section<Self::Posts>>,
-    Self::Timestamp: Display,
+    Self::Timestamp: Time,
 {
+    type GoneFeed;
+    type GoneFeeds;
     type PostHolder;
     type Posts;
     type RefreshTimestamps;
@@ -83,47 +433,86 @@ where
     fn post_sections(&self) -> Self::Sections;
 
     fn refresh_timestamps(&self) -> Self::RefreshTimestamps;
+
+    /// The remote feeds that the server said are gone, and so are not shown.
+    fn gone_feeds(&self) -> Self::GoneFeeds;
 }
 
 fn controls<'data>(
     output: &mut impl Output,
+    preferences: &Preferences,
+    csrf_token: &str,
     data: &impl Data<'data>
 ) -> Result {
     use RefreshKind::*;
 
+    let csrf_input = CsrfInput(csrf_token);
+
     for r_t in data.refresh_timestamps() {
         let (label, refresh_key) = match r_t.kind {
             Local => ("Refresh Local Posts", REFRESH_LOCAL),
             Remote => ("Refresh Remote Posts", REFRESH_REMOTE),
         };
-        let timestamp = r_t.timestamp;
-
         write!(
             output,
             "\
-            <form>\
-              <button \
-                type='submit' \
-                title='Fresh as of {timestamp} (taking oldest)'\
-              >\
-                {label}\
-              </button>\
+            <form method='post' action='/'>\
+              <button type='submit'>{label}</button>\
               <input type='hidden' name='{refresh_key}'>\
-            </form>\
-            "
+              {csrf_input}\
+              <span class='refreshed'>"
         )?;
+
+        // Taking the oldest, since that is how fresh everything is.
+        match &r_t.timestamp {
+            Some(timestamp) => write!(
+                output,
+                "Refreshed {}",
+                ShownTime(timestamp, preferences.time_format)
+            )?,
+            None => write!(output, "Not refreshed yet")?,
+        }
+
+        write!(output, "</span></form>")?;
     }
 
+    write!(
+        output,
+        "\
+        <form method='post' action='/'>\
+          <button \
+            type='submit' \
+            title='Re-read the list of remote feeds from the data directory'\
+          >\
+            Reload Remote Feed List\
+          </button>\
+          <input type='hidden' name='{REFRESH_REMOTE_URLS}'>\
+          {csrf_input}\
+        </form>\
+        "
+    )?;
+
     write!(output, "<div><a href='{LOCAL_ADD}'>Add local entry</a></div>")?;
-    write!(output, "<div><a href='{REMOTE_ADD}'>Add remote feed</a></div>")
+    write!(output, "<div><a href='{REMOTE_ADD}'>Add remote feed</a></div>")?;
+    write!(
+        output,
+        "<div><a href='{RESTORE}'>Restore a previous version of a local feed</a></div>"
+    )
 }
 
 fn feeds<'data>(
     output: &mut impl Output,
+    preferences: &Preferences,
     data: &impl Data<'data>
 ) -> Result {
     use SectionKind::*;
 
+    let open = if preferences.expand_sections {
+        " open"
+    } else {
+        ""
+    };
+
     for section in data.post_sections() {
         let (name, letter) = match section.kind {
             Local => ("local posts", 'L'),
@@ -132,21 +521,49 @@ fn feeds<'data>(
 
         write!(
             output,
-            "<details>\
+            "<details{open}>\
                 <summary>{name}</summary>\
             "
         )?;
 
         for (i, post) in section.posts.enumerate() {
-            let source = post.source();
-            write!(output, "#{letter}{i} &ndash; <small>{source}</small>")?;
+            let id = post.id();
+            let sources = post.sources();
+            let time = post.time();
 
             let post = post.get_post();
 
             let mut links = post.links;
 
+            let primary_link = Escaped(
+                links.first().map(|link| link.as_ref()).unwrap_or_default()
+            );
+
+            write!(
+                output,
+                "<article class='post' id='{id}' data-link='{primary_link}'>\
+                    <a href='#{id}'>#{letter}{i}</a> &ndash; <small>"
+            )?;
+
+            for (i, source) in sources.enumerate() {
+                if i > 0 {
+                    write!(output, ", ")?;
+                }
+                write!(output, "{source}")?;
+            }
+
+            write!(output, "</small>")?;
+
+            if let Some(time) = &time {
+                write!(
+                    output,
+                    " &ndash; <small>{}</small>",
+                    ShownTime(time, preferences.time_format)
+                )?;
+            }
+
             if let Some(title) = post.title {
-                if let Some(link) = links.get(0) {
+                if let Some(link) = links.first() {
                     let link = link.as_ref();
                     write!(output, "<h2><a href=\"{link}\">{title}</a></h2>")?;
                     links = &links[1..];
@@ -167,6 +584,8 @@ fn feeds<'data>(
                 let link = link.as_ref();
                 write!(output, "<a href=\"{link}\">{}</a>", i + 1)?;
             }
+
+            write!(output, "</article>")?;
         }
 
         write!(output, "</details>")?;
@@ -177,20 +596,96 @@ fn feeds<'data>(
 
 pub fn home_page<'data>(
     output: &mut impl Output,
+    preferences: &Preferences,
+    csrf_token: &str,
     data: &impl Data<'data>
 ) -> Result {
     main_template(
         output,
+        preferences,
         |o| {
-            controls(o, data)?;
+            controls(o, preferences, csrf_token, data)?;
+
+            gone_feeds(o, data)?;
+
+            if preferences.live_updates {
+                write!(
+                    o,
+                    "\
+                    <div id='new-posts' hidden>\
+                        <button type='button'></button>\
+                    </div>\
+                    <ul id='feed-errors' hidden></ul>"
+                )?;
+            }
+
+            write!(o, "<div id='feeds'>")?;
 
-            feeds(o, data)?;
+            feeds(o, preferences, data)?;
 
-            footer(o, data)
+            write!(o, "</div>")?;
+
+            if preferences.keyboard_shortcuts {
+                keyboard_help(o, preferences)?;
+            }
+
+            if preferences.live_updates {
+                write!(
+                    o,
+                    "<script src='{LIVE_SCRIPT}?v={:x}' defer></script>",
+                    preferences.live_updates_script_version,
+                )?;
+            }
+
+            footer(o, preferences, csrf_token, data)
         }
     )
 }
 
+fn gone_feeds<'data>(
+    output: &mut impl Output,
+    data: &impl Data<'data>
+) -> Result {
+    let mut gone_feeds = data.gone_feeds().peekable();
+
+    if gone_feeds.peek().is_none() {
+        return Ok(())
+    }
+
+    write!(
+        output,
+        "\
+        <div id='gone-feeds'>\
+        <p>These feeds are gone, so they are no longer fetched or shown:</p>\
+        <ul>"
+    )?;
+
+    for url in gone_feeds {
+        write!(output, "<li>{url}</li>")?;
+    }
+
+    write!(
+        output,
+        "\
+        </ul>\
+        <p>\
+          Remove them from <code>remote-feeds</code>, or from \
+          <code>gone-feeds</code> to try them again.\
+        </p>\
+        </div>"
+    )
+}
+
+/// Just the posts from the home page, for replacing the ones on an already
+/// loaded home page.
+pub fn posts_fragment<'data>(
+    output: &mut impl Output,
+    preferences: &Preferences,
+    data: &impl Data<'data>
+) -> Result {
+    feeds(output, preferences, data)
+}
+
 pub trait Path
 where
     Self::Display: Display
@@ -236,6 +731,8 @@ pub fn local_add_form<
     S: AsRef<str>,
 >(
     output: &mut impl Output,
+    preferences: &Preferences,
+    csrf_token: &str,
     local_add_targets: impl Iterator<Item = Trget>,
     root_display: &impl RootDisplay,
     previous: Option<(
@@ -252,26 +749,13 @@ pub fn local_add_form<
 ) -> Result {
     main_template(
         output,
+        preferences,
         |o| {
-            // TODO move this to the head tag, if it matters later, I guess?
-            // The perf difference if any, doesn't seem significant.
-            // TODO reduce duplication with similar style tags, if this gets
-            // copy-pasted again?
-            write!(
-                o,
-                "<style>\
-                     form {{ display: table; }}\
-                        p {{ display: table-row; }}\
-                   select {{ display: table-cell; }}\
-                    label {{ display: table-cell; text-align: right }}\
-                    input {{ display: table-cell; }}\
-                </style>"
-            )?;
-
             write!(
                 o,
                 "\
-                <form>"
+                <form class='fields'>{}",
+                CsrfInput(csrf_token)
             )?;
 
             if let Some((_, error_message)) = &previous {
@@ -309,7 +793,7 @@ pub fn local_add_form<
                form.summary,
                form.content,
                form.links.get(1).map(|s| s.as_ref()).unwrap_or_default(),
-               form.links.get(0).map(|s| s.as_ref()).unwrap_or_default(),
+               form.links.first().map(|s| s.as_ref()).unwrap_or_default(),
             )).unwrap_or_default();
 
             write!(
@@ -356,7 +840,7 @@ pub fn local_add_form<
             </form>"
             )?;
 
-            footer(o, root_display)
+            footer(o, preferences, csrf_token, root_display)
         }
     )
 }
@@ -367,6 +851,8 @@ pub struct RemoteFeedAddForm<'url> {
 
 pub fn remote_feed_add_form<'url>(
     output: &mut impl Output,
+    preferences: &Preferences,
+    csrf_token: &str,
     root_display: &impl RootDisplay,
     previous: Option<(
         RemoteFeedAddForm<'url>,
@@ -375,25 +861,13 @@ pub fn remote_feed_add_form<'url>(
 ) -> Result {
     main_template(
         output,
+        preferences,
         |o| {
-            // TODO move this to the head tag, if it matters later, I guess?
-            // The perf difference if any, doesn't seem significant.
-            // TODO reduce duplication with similar style tags, if this gets
-            // copy-pasted again?
-            write!(
-                o,
-                "<style>\
-                     form {{ display: table; }}\
-                        p {{ display: table-row; }}\
-                    label {{ display: table-cell; text-align: right }}\
-                    input {{ display: table-cell; }}\
-                </style>"
-            )?;
-
             write!(
                 o,
                 "\
-                <form>"
+                <form class='fields'>{}",
+                CsrfInput(csrf_token)
             )?;
 
             if let Some((_, error_message)) = &previous {
@@ -420,41 +894,383 @@ pub fn remote_feed_add_form<'url>(
             </form>"
             )?;
 
-            footer(o, root_display)
+            footer(o, preferences, csrf_token, root_display)
+        }
+    )
+}
+
+pub struct LoginForm<'name> {
+    pub name: &'name str,
+}
+
+pub fn login_page<'name>(
+    output: &mut impl Output,
+    preferences: &Preferences,
+    previous: Option<(
+        LoginForm<'name>,
+        &str
+    )>,
+) -> Result {
+    main_template(
+        output,
+        preferences,
+        |o| {
+            write!(
+                o,
+                "\
+                <form class='fields' action='{LOGIN}' method='post'>"
+            )?;
+
+            if let Some((_, error_message)) = &previous {
+                write!(o, "{error_message}")?;
+            }
+
+            let form = previous.map(|(form, _)| form);
+
+            let name = Escaped(form.map(|form| form.name).unwrap_or_default());
+
+            write!(
+                o,
+                "\
+                <p>\
+                    <label for='{USER_NAME}'>Name</label>\
+                    <input \
+                        name='{USER_NAME}' id='{USER_NAME}' value='{name}'\
+                        autocomplete='username'\
+                    >\
+                </p>\
+                <p>\
+                    <label for='{PASSWORD}'>Password</label>\
+                    <input \
+                        type='password' name='{PASSWORD}' id='{PASSWORD}'\
+                        autocomplete='current-password'\
+                    >\
+                </p>\
+                <p>\
+                    <label for='submit'></label>\
+                    <input type='submit' id='submit' value='Log in'>\
+                </p>\
+            </form>"
+            )
         }
     )
 }
 
 pub fn local_add_form_success(
     output: &mut impl Output,
+    preferences: &Preferences,
 ) -> Result {
     main_template(
         output,
+        preferences,
         |o| write!(o, "Successfully added local post")
     )
 }
 
 pub fn remote_feed_add_form_success(
     output: &mut impl Output,
+    preferences: &Preferences,
 ) -> Result {
     main_template(
         output,
+        preferences,
         |o| write!(o, "Successfully added remote feed")
     )
 }
 
-fn footer<'data>(
+/// A local feed, and the previous versions of it that can be restored.
+pub struct BackedUpFeed<Trget, Versions> {
+    pub target: Trget,
+    /// Newest first.
+    pub versions: Versions,
+}
+
+/// A previous version of a file.
+pub struct Version<Id, T> {
+    pub id: Id,
+    /// When this version was replaced by a newer one.
+    pub replaced_at: T,
+}
+
+pub fn restore_page<Trget, Versions, Id, T>(
+    output: &mut impl Output,
+    preferences: &Preferences,
+    csrf_token: &str,
+    feeds: impl Iterator<Item = BackedUpFeed<Trget, Versions>>,
+    root_display: &impl RootDisplay,
+    error_message: Option<&str>,
+) -> Result
+where
+    Trget: Target,
+    Versions: Iterator<Item = Version<Id, T>>,
+    Id: Display,
+    T: Time,
+{
+    main_template(
+        output,
+        preferences,
+        |o| {
+            if let Some(error_message) = error_message {
+                write!(o, "<p>{error_message}</p>")?;
+            }
+
+            write!(
+                o,
+                "<p>A copy of a local feed is kept each time it is changed. \
+                Restoring one keeps a copy of the current version too, so it \
+                can be undone.</p>"
+            )?;
+
+            for feed in feeds {
+                let label = feed.target.label();
+
+                write!(
+                    o,
+                    "\
+                    <form method='post' action='{RESTORE}'>\
+                        {csrf_input}\
+                        <input type='hidden' name='{TARGET}' value='{value}'>\
+                        <fieldset>\
+                            <legend>{label}</legend>",
+                    csrf_input = CsrfInput(csrf_token),
+                    value = feed.target.value(),
+                )?;
+
+                let mut any = false;
+
+                for (i, version) in feed.versions.enumerate() {
+                    let checked = if i == 0 { " checked" } else { "" };
+
+                    write!(
+                        o,
+                        "<p><label>\
+                            <input type='radio' name='{VERSION}' value='{}'{checked}> \
+                            Replaced {}\
+                        </label></p>",
+                        version.id,
+                        ShownTime(&version.replaced_at, preferences.time_format),
+                    )?;
+
+                    any = true;
+                }
+
+                if any {
+                    write!(o, "<button type='submit'>Restore</button>")?;
+                } else {
+                    write!(o, "<p>No previous versions</p>")?;
+                }
+
+                write!(o, "</fieldset></form>")?;
+            }
+
+            footer(o, preferences, csrf_token, root_display)
+        }
+    )
+}
+
+pub fn restore_success(
+    output: &mut impl Output,
+    preferences: &Preferences,
+    label: impl Display,
+) -> Result {
+    main_template(
+        output,
+        preferences,
+        |o| write!(o, "Successfully restored {label}")
+    )
+}
+
+pub enum RefreshJobStatus {
+    Running,
+    /// Cancelled, but still waiting on a feed that was already being fetched.
+    Cancelling,
+    Finished,
+    Cancelled,
+}
+
+pub enum FeedRefreshStatus<'error> {
+    Pending,
+    InFlight,
+    Done,
+    /// Done, and the feed has permanently moved to this URL.
+    Moved(&'error str),
+    Failed(&'error str),
+    /// Skipped, because it failed this many times in a row recently.
+    BackingOff { failures: u32 },
+    /// The server said the feed is gone for good.
+    Gone,
+}
+
+/// Shows how far along a refresh of the remote feeds is. While the refresh is
+/// still going, the page reloads itself every second.
+pub fn refresh_progress_page<'feeds>(
+    output: &mut impl Output,
+    preferences: &Preferences,
+    csrf_token: &str,
+    job_status: RefreshJobStatus,
+    feeds: impl Iterator<Item = (&'feeds str, FeedRefreshStatus<'feeds>)>,
+) -> Result {
+    use RefreshJobStatus::*;
+
+    main_template(
+        output,
+        preferences,
+        |o| {
+            let heading = match job_status {
+                Running | Cancelling => {
+                    // The tag omission rules put this in the head, since it comes
+                    // before anything that has to be in the body.
+                    write!(o, "<meta http-equiv='refresh' content='1'>")?;
+
+                    if let Running = job_status {
+                        "Refreshing remote feeds"
+                    } else {
+                        "Cancelling refresh"
+                    }
+                }
+                Finished => "Refresh finished",
+                Cancelled => "Refresh cancelled",
+            };
+
+            write!(o, "<h1>{heading}</h1><table class='refresh-progress'>")?;
+
+            for (url, status) in feeds {
+                use FeedRefreshStatus::*;
+
+                let (class, label) = match status {
+                    Pending => ("pending", "Pending"),
+                    InFlight => ("in-flight", "Fetching"),
+                    Done | Moved(_) => ("done", "Done"),
+                    Failed(_) => ("failed", "Failed"),
+                    BackingOff { .. } => ("backing-off", "Skipped"),
+                    Gone => ("failed", "Gone"),
+                };
+
+                write!(
+                    o,
+                    "<tr class='{class}'><td>{}</td><td>{label}",
+                    Escaped(url)
+                )?;
+
+                match status {
+                    Moved(to) => write!(o, ", and moved to {}", Escaped(to))?,
+                    Failed(error) => write!(o, ": {}", Escaped(error))?,
+                    BackingOff { failures } => write!(
+                        o,
+                        " after failing {failures} time(s) in a row"
+                    )?,
+                    Gone => write!(o, ", so it will not be fetched again")?,
+                    Pending | InFlight | Done => {}
+                }
+
+                write!(o, "</td></tr>")?;
+            }
+
+            write!(o, "</table>")?;
+
+            match job_status {
+                Running => write!(
+                    o,
+                    "\
+                    <form method='post' action='{REFRESH_CANCEL}'>\
+                      <button type='submit'>Cancel</button>\
+                      {}\
+                    </form>",
+                    CsrfInput(csrf_token)
+                ),
+                Cancelling => Ok(()),
+                Finished | Cancelled => write!(o, "<a href='/'>Back to posts</a>"),
+            }
+        }
+    )
+}
+
+/// The help panel is hidden unless the script shows it, so that it does not
+/// mention keys that do nothing when JavaScript is disabled.
+fn keyboard_help(
     o: &mut impl Output,
-    root_display: &impl RootDisplay
+    preferences: &Preferences,
 ) -> Result {
     write!(
         o,
-        "<footer>{}</footer>",
-        root_display.root_display()
+        "\
+        <aside id='keyboard-help' hidden>\
+            <h2>Keyboard Shortcuts</h2>\
+            <dl>\
+                <dt>j</dt><dd>Next post</dd>\
+                <dt>k</dt><dd>Previous post</dd>\
+                <dt>o</dt><dd>Open the post's link, and mark it read</dd>\
+                <dt>m</dt><dd>Toggle read</dd>\
+                <dt>s</dt><dd>Toggle saved</dd>\
+                <dt>?</dt><dd>Toggle this help</dd>\
+            </dl>\
+        </aside>\
+        <script src='{KEYBOARD_SCRIPT}?v={:x}' defer></script>",
+        preferences.keyboard_script_version,
     )
 }
 
-/// URL param keys.
+/// Displays the string with the characters that are special in HTML, (including
+/// inside attribute values), escaped.
+struct Escaped<'s>(&'s str);
+
+impl Display for Escaped<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '&' => f.write_str("&amp;")?,
+                '<' => f.write_str("&lt;")?,
+                '>' => f.write_str("&gt;")?,
+                '"' => f.write_str("&quot;")?,
+                '\'' => f.write_str("&#39;")?,
+                _ => core::fmt::Write::write_char(f, c)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The hidden form field that proves a form submission came from one of our own
+/// pages, rather than some other site.
+struct CsrfInput<'token>(&'token str);
+
+impl Display for CsrfInput<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "<input type='hidden' name='{CSRF_TOKEN}' value='{}'>",
+            Escaped(self.0)
+        )
+    }
+}
+
+fn footer(
+    o: &mut impl Output,
+    preferences: &Preferences,
+    csrf_token: &str,
+    root_display: &impl RootDisplay
+) -> Result {
+    write!(o, "<footer>{}", root_display.root_display())?;
+
+    // A form, since logging out is only done with a POST.
+    if preferences.logins {
+        write!(
+            o,
+            "\
+            <form method='post' action='{LOGOUT}'>\
+              <button type='submit'>Log out</button>\
+              {}\
+            </form>",
+            CsrfInput(csrf_token),
+        )?;
+    }
+
+    write!(o, "</footer>")
+}
+
+/// URL param keys. These are also used as form element names, when the params
+/// are sent in a POST body.
 pub mod param_keys {
     pub const REFRESH_LOCAL: &str = "refresh-local";
     pub const REFRESH_REMOTE: &str = "refresh-remote";
@@ -466,6 +1282,16 @@ use param_keys::*;
 pub mod page_names {
     pub const LOCAL_ADD: &str = "/local-add";
     pub const REMOTE_ADD: &str = "/remote-add";
+    pub const LOGIN: &str = "/login";
+    pub const LOGOUT: &str = "/logout";
+    pub const STYLESHEET: &str = "/style.css";
+    pub const KEYBOARD_SCRIPT: &str = "/keyboard.js";
+    pub const LIVE_SCRIPT: &str = "/live.js";
+    pub const POSTS: &str = "/posts";
+    pub const EVENTS: &str = "/events";
+    pub const REFRESH: &str = "/refresh";
+    pub const REFRESH_CANCEL: &str = "/refresh/cancel";
+    pub const RESTORE: &str = "/restore";
 }
 use page_names::*;
 
@@ -479,25 +1305,42 @@ pub mod form_names {
     pub const LINK: &str = "link";
 
     pub const FEED_URL: &str = "feed-url";
+
+    pub const VERSION: &str = "version";
+
+    pub const CSRF_TOKEN: &str = "csrf-token";
+
+    pub const USER_NAME: &str = "user-name";
+    pub const PASSWORD: &str = "password";
 }
 use form_names::*;
 
 fn main_template<O>(
     output: &mut O,
+    preferences: &Preferences,
     body: impl FnOnce(&mut O) -> Result,
 ) -> Result
 where O: Output
 {
+    let theme = match preferences.theme {
+        Theme::Auto => "auto",
+        Theme::Dark => "dark",
+        Theme::Light => "light",
+    };
+    let version = preferences.stylesheet_version;
+
     // Many tags can officially be omitted. If the browsers display it properly, why
     // send extra bytes?
     // See https://html.spec.whatwg.org/multipage/syntax.html#syntax-tag-omission
-    const HEADER: &str = "\
+    writeln!(
+        output,
+        "\
         <!DOCTYPE HTML>\
-        <style> * { color: #eee; background-color: #222 } </style>
-        <title>RRR</title>\n\
-    ";
-
-    output.write_str(HEADER)?;
+        <html data-theme='{theme}'>\
+        <link rel='stylesheet' href='{STYLESHEET}?v={version:x}'>\
+        <title>RRR</title>\
+        "
+    )?;
 
     body(output)?;
 
@@ -1,6 +1,6 @@
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::path::PathBuf;
-use rouille::{start_server, try_or_400, Request, Response};
+use rouille::{try_or_400, Request, Response};
 
 use logic::Method;
 
@@ -8,14 +8,49 @@ type Res<A> = Result<A, Box<dyn std::error::Error>>;
 
 const APP_NAME: &str = env!("CARGO_PKG_NAME");
 
-fn main() -> Res<()> {
-    let result = inner_main();
+const USAGE: &str = "\
+usage: rrr [address] [data_dir]
+       rrr add-feed <url> [--data-dir <data_dir>]
+       rrr list-feeds [--data-dir <data_dir>]
+       rrr refresh [--data-dir <data_dir>]
+       rrr post --feed <file> [--title <title>] [--summary <summary>]
+                [--content <content>] [--link <link>]... [--data-dir <data_dir>]
+       rrr export [--data-dir <data_dir>]
+       rrr hash-password < password_file";
 
-    if result.is_err() {
-        println!("usage: {APP_NAME} <address>");
+const SUBCOMMANDS: &[&str] = &[
+    "add-feed",
+    "list-feeds",
+    "refresh",
+    "post",
+    "export",
+    "hash-password",
+];
+
+fn main() {
+    if let Err(e) = inner_main() {
+        println!("{USAGE}");
+
+        // Printing with `Display` rather than the `Debug` that returning the
+        // error from `main` would use, gives much more readable messages for
+        // things like config file errors.
+        eprintln!("Error: {e}");
+
+        std::process::exit(1);
     }
+}
 
-    result
+enum Command {
+    /// If no address is given, the one from the config file is used.
+    Serve(Option<String>),
+    AddFeed(String),
+    ListFeeds,
+    Refresh,
+    Post {
+        feed: PathBuf,
+        post: logic::Post,
+    },
+    Export,
 }
 
 fn inner_main() -> Res<()> {
@@ -23,54 +58,304 @@ fn inner_main() -> Res<()> {
 
     args.next(); // exe name
 
-    let addr = if let Some(addr_str) = args.next() {
-        if let Some(addr) = first_addr(&addr_str) {
-            addr
-        } else {
-            first_addr((addr_str, 8080))
-                .ok_or_else(|| "No valid socket address found")?
-        }
-    } else {
-        return Err("No socket address found".into())
-    };
+    let mut data_dir_override = None;
+
+    let command = match args.next().as_deref() {
+        Some("add-feed") => {
+            let mut url = None;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--data-dir" => {
+                        data_dir_override = Some(
+                            args.next().ok_or("No value found for --data-dir")?
+                        );
+                    }
+                    _ if url.is_none() => { url = Some(arg); }
+                    _ => return Err(format!("Unexpected argument: {arg}").into()),
+                }
+            }
+
+            Command::AddFeed(url.ok_or("No feed URL found")?)
+        },
+        Some(name @ ("list-feeds" | "refresh" | "export")) => {
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--data-dir" => {
+                        data_dir_override = Some(
+                            args.next().ok_or("No value found for --data-dir")?
+                        );
+                    }
+                    _ => return Err(format!("Unexpected argument: {arg}").into()),
+                }
+            }
+
+            match name {
+                "list-feeds" => Command::ListFeeds,
+                "refresh" => Command::Refresh,
+                _ => Command::Export,
+            }
+        },
+        Some("hash-password") => {
+            if let Some(arg) = args.next() {
+                return Err(format!("Unexpected argument: {arg}").into())
+            }
+
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(&['\r', '\n'][..]);
+
+            println!("{}", access::hash_password(password)?);
 
-    println!("Address: {addr}");
+            return Ok(())
+        },
+        Some("post") => {
+            let mut feed = None;
+            let mut post = logic::Post {
+                id: None,
+                title: None,
+                summary: None,
+                content: None,
+                links: Vec::with_capacity(1),
+                published: None,
+                updated: None,
+            };
 
-    let data_dir = if let Some(data_dir_override) = args.next()
+            while let Some(arg) = args.next() {
+                let mut value = || args.next()
+                    .ok_or_else(|| format!("No value found for {arg}"));
+
+                match arg.as_str() {
+                    "--data-dir" => { data_dir_override = Some(value()?); }
+                    "--feed" => { feed = Some(PathBuf::from(value()?)); }
+                    "--title" => { post.title = Some(value()?); }
+                    "--summary" => { post.summary = Some(value()?); }
+                    "--content" => { post.content = Some(value()?); }
+                    "--link" => { post.links.push(value()?); }
+                    _ => return Err(format!("Unexpected argument: {arg}").into()),
+                }
+            }
+
+            Command::Post {
+                feed: feed.ok_or("No --feed found")?,
+                post,
+            }
+        },
+        addr_str => {
+            if let Some(arg) = addr_str
+                .filter(|arg| arg.starts_with('-') || looks_like_subcommand(arg))
+            {
+                return Err(format!(
+                    "Unexpected argument: {arg}. Expected an address, or one of: {}",
+                    SUBCOMMANDS.join(", "),
+                ).into())
+            }
+
+            data_dir_override = args.next();
+
+            Command::Serve(
+                addr_str
+                    // Treat an empty arg as no arg.
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_owned)
+            )
+        },
+    };
+
+    let data_dir = if let Some(data_dir_override) = data_dir_override
         // Treat an empty arg as no arg.
-        .and_then(|s| (!s.is_empty()).then(|| s))
+        .and_then(|s| (!s.is_empty()).then_some(s))
     {
-        PathBuf::try_from(data_dir_override)?
+        PathBuf::from(data_dir_override)
     } else {
         directories::ProjectDirs::from("com", "ryanwiedemann", APP_NAME)
             // The `directories` docs says this only returns none when
             // "no valid home directory path could be retrieved from
             // the operating system."
-            .ok_or_else(|| "No valid home directory path found")?
+            .ok_or("No valid home directory path found")?
             .data_dir()
             .to_owned()
     };
 
-    let state = logic::State::try_from(data_dir)?;
+    let addr_str = match command {
+        Command::Serve(addr_str) => addr_str,
+        command => return run_headless(command, data_dir),
+    };
+
+    let access = access::load(&data_dir)?;
+
+    let mut state = logic::State::open(data_dir.clone())?;
+
+    if access.is_some() {
+        state = state.with_logins();
+    }
+
+    // Held until the server stops, which is when the process exits.
+    let _lock = state.lock_data_dir()?;
 
     {
         let displayed_dir = state.root_display();
         println!("Data Directory: {displayed_dir}");
     }
 
-    start(addr, state)
+    let server_config = &state.config().server;
+
+    let addr_str = addr_str
+        .or_else(|| server_config.address.clone())
+        .ok_or("No socket address found")?;
+
+    let addr = if let Some(addr) = first_addr(addr_str.as_str()) {
+        addr
+    } else {
+        first_addr((addr_str.as_str(), server_config.port))
+            .ok_or("No valid socket address found")?
+    };
+
+    let tls = tls_pair(&state.config().server.tls, &data_dir, addr)?;
+
+    println!(
+        "Address: {}://{addr}",
+        if tls.is_some() { "https" } else { "http" }
+    );
+
+    if access.is_none() && !addr.ip().is_loopback() {
+        println!(
+            "Warning: No {} found in the data directory, so anyone who can reach \
+            {addr} can read and change your feeds.",
+            access::FILE_NAME,
+        );
+    }
+
+    if state.config().refresh.remote_on_startup {
+        // The other feeds are still worth showing, and the failing ones will be
+        // retried on later refreshes.
+        if let Err(e) = state.fetch_remote_posts() {
+            eprintln!("{e}");
+        }
+    }
+
+    match start(addr, state, access, tls)? {}
+}
+
+fn tls_pair(
+    tls: &logic::config::Tls,
+    data_dir: &std::path::Path,
+    addr: SocketAddr,
+) -> Res<Option<certificate::Pair>> {
+    if !tls.enabled {
+        return Ok(None)
+    }
+
+    let pair = match (&tls.certificate, &tls.private_key) {
+        (None, None) => {
+            let mut names = vec!["localhost".to_owned()];
+            let ip = addr.ip();
+            if !ip.is_unspecified() && !ip.is_loopback() {
+                names.push(ip.to_string());
+            }
+
+            certificate::load_or_generate_self_signed(
+                &data_dir.join(certificate::CERTIFICATE_FILE_NAME),
+                &data_dir.join(certificate::PRIVATE_KEY_FILE_NAME),
+                names,
+            )?
+        }
+        (Some(certificate), Some(private_key)) => {
+            certificate::load(
+                &data_dir.join(certificate),
+                &data_dir.join(private_key),
+            )?
+        }
+        _ => {
+            return Err(
+                "Both server.tls.certificate and server.tls.private_key must be \
+                set, or neither".into()
+            )
+        }
+    };
+
+    Ok(Some(pair))
+}
+
+/// Runs a command that works with the data directory without starting a server.
+fn run_headless(command: Command, data_dir: PathBuf) -> Res<()> {
+    let state = logic::State::open(data_dir)?;
+
+    match command {
+        Command::Serve(_) => {
+            return Err("Cannot serve headlessly".into())
+        }
+        Command::AddFeed(url) => {
+            state.add_remote_feed(url)?;
+        }
+        Command::ListFeeds => {
+            for url in state.remote_feed_urls() {
+                println!("remote\t{url}");
+            }
+
+            for path in state.local_feed_paths() {
+                println!("local\t{path}");
+            }
+        }
+        Command::Refresh => {
+            state.refresh()?;
+
+            let (local, remote) = state.post_counts();
+            println!("Loaded {local} local posts and {remote} remote posts");
+        }
+        Command::Post { feed, post } => {
+            state.add_local_post(feed, post)?;
+        }
+        Command::Export => {
+            state.export_opml(std::io::stdout().lock())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `arg` is more likely a mistyped subcommand, or a request for help,
+/// than an address, so that it does not quietly start a server instead.
+fn looks_like_subcommand(arg: &str) -> bool {
+    arg.bytes().all(|b| b.is_ascii_lowercase() || b == b'-')
+        && (
+            arg == "help"
+            || SUBCOMMANDS.iter().any(|name| edit_distance(arg, name) <= name.len() / 3)
+        )
+}
+
+/// The number of bytes that need to be inserted, removed or replaced to turn
+/// `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+
+    for (i, a_byte) in a.bytes().enumerate() {
+        current[0] = i + 1;
+
+        for (j, b_byte) in b.bytes().enumerate() {
+            current[j + 1] = (previous[j] + usize::from(a_byte != b_byte))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+        }
+
+        std::mem::swap(&mut previous, &mut current);
+    }
+
+    previous[b.len()]
 }
 
 fn first_addr(to_addrs: impl ToSocketAddrs) -> Option<SocketAddr> {
     to_addrs.to_socket_addrs().ok()?.next()
 }
 
-#[repr(transparent)]
-struct TaskSpec<'request>(&'request Request);
+struct TaskSpec<'request> {
+    request: &'request Request,
+    requester: &'request Requester,
+}
 
 impl <'request> logic::TaskSpec for TaskSpec<'request> {
     fn method(&self) -> Method {
-        match self.0.method() {
+        match self.request.method() {
             "GET" => Method::Get,
             "POST" => Method::Post,
             _ => Method::Other,
@@ -78,18 +363,33 @@ impl <'request> logic::TaskSpec for TaskSpec<'request> {
     }
 
     fn url_suffix(&self) -> String {
-        self.0.url()
+        self.request.url()
     }
 
     fn query_param(&self, key: &str) -> Option<String> {
-        self.0.get_param(key)
+        self.request.get_param(key)
+    }
+
+    fn csrf(&self) -> logic::Csrf<'_> {
+        match &self.requester.csrf_token {
+            Some(token) => logic::Csrf::Token(token),
+            None => logic::Csrf::NotNeeded,
+        }
+    }
+
+    type RefreshFormError = rouille::input::post::PostError;
+    fn refresh_form(&self)
+    -> Result<Vec<(String, String)>, Self::RefreshFormError> {
+        rouille::input::post::raw_urlencoded_post_input(
+            self.request
+        )
     }
 
     type LocalAddFormError = rouille::input::post::PostError;
     fn local_add_form(&self)
     -> Result<Vec<(String, String)>, Self::LocalAddFormError> {
         rouille::input::post::raw_urlencoded_post_input(
-            &self.0
+            self.request
         )
     }
 
@@ -97,39 +397,306 @@ impl <'request> logic::TaskSpec for TaskSpec<'request> {
     fn remote_feed_add_form(&self)
     -> Result<Vec<(String, String)>, Self::RemoteFeedAddFormError> {
         rouille::input::post::raw_urlencoded_post_input(
-            &self.0
+            self.request
+        )
+    }
+
+    type RestoreFormError = rouille::input::post::PostError;
+    fn restore_form(&self)
+    -> Result<Vec<(String, String)>, Self::RestoreFormError> {
+        rouille::input::post::raw_urlencoded_post_input(
+            self.request
         )
     }
 }
 
-fn start(addr: SocketAddr, state: logic::State) -> ! {
-    let state_mutex = std::sync::Mutex::new(state);
+/// Who made a request, as far as we can tell.
+struct Requester {
+    role: access::Role,
+    /// `None` if the request could not have been made by a browser on behalf of
+    /// some other site.
+    csrf_token: Option<String>,
+}
 
-    start_server(addr, move |request| {
-        match state_mutex.lock() {
-            Ok(ref mut state) => {
-                let task: logic::Task = try_or_400!(
-                    logic::extract_task(&TaskSpec(&request), state)
-                );
+fn start(
+    addr: SocketAddr,
+    state: logic::State,
+    access: Option<access::Access>,
+    tls: Option<certificate::Pair>,
+) -> Res<std::convert::Infallible> {
+    // Without an access file there are no sessions, so everyone shares a token
+    // that lasts as long as the process does. Other sites still cannot read it.
+    let shared_csrf_token = access::random_token()?;
 
-                match state.perform(task) {
-                    Ok(output) => extract_response(output),
-                    Err(e) => {
-                        Response::text(e.to_string()).with_status_code(500)
-                    }
+    // Shared with any background jobs that the state starts.
+    let state = std::sync::Arc::new(state);
+
+    // Like the lock on the data directory, this is kept until the server stops.
+    let _watcher = if state.config().refresh.watch {
+        Some(state.watch_data_dir()?)
+    } else {
+        None
+    };
+
+    let handler = move |request: &Request| {
+        let requester = match &access {
+            None => Requester {
+                role: access::Role::ReadWrite,
+                csrf_token: Some(shared_csrf_token.clone()),
+            },
+            Some(access) => match authenticate(request, access, &state) {
+                Ok(requester) => requester,
+                Err(response) => return response,
+            },
+        };
+
+        let task: logic::Task = try_or_400!(
+            logic::extract_task(
+                &TaskSpec { request, requester: &requester },
+                &state
+            )
+        );
+
+        // A refresh can still fetch what is shown, it just will not save it.
+        let task = if requester.role.can_write() {
+            task
+        } else {
+            task.read_only()
+        };
+
+        if task.writes() && !requester.role.can_write() {
+            return Response::text("Read-only access").with_status_code(403)
+        }
+
+        let csrf_token = requester.csrf_token.as_deref()
+            .unwrap_or_default();
+
+        match state.perform(task, csrf_token) {
+            Ok(output) => extract_response(output),
+            Err(e) => {
+                Response::text(e.to_string()).with_status_code(500)
+            }
+        }
+    };
+
+    let server = match tls {
+        Some(pair) => rouille::Server::new_ssl(
+            addr,
+            handler,
+            pair.certificate,
+            pair.private_key,
+        ),
+        None => rouille::Server::new(addr, handler),
+    }.map_err(|e| e.to_string())?;
+
+    server.run();
+
+    Err("The server stopped unexpectedly".into())
+}
+
+/// Returns the requester if they are logged in, or otherwise the response that
+/// should be sent instead of performing the request. Requests to log in or out
+/// are also handled here.
+fn authenticate(
+    request: &Request,
+    access: &access::Access,
+    state: &logic::State,
+) -> Result<Requester, Response> {
+    use logic::{form_names, page_names};
+
+    let session_id = rouille::input::cookies(request)
+        .find(|&(name, _)| name == access::SESSION_COOKIE)
+        .map(|(_, id)| id);
+
+    match (request.method(), request.url().as_str()) {
+        // The login page needs this, and there's nothing secret in it.
+        ("GET", page_names::STYLESHEET) => {
+            return Ok(Requester {
+                role: access::Role::ReadOnly,
+                csrf_token: None,
+            })
+        }
+        ("GET", page_names::LOGIN) => {
+            return Err(login_page_response(state, None))
+        }
+        ("POST", page_names::LOGIN) => {
+            let pairs = rouille::input::post::raw_urlencoded_post_input(request)
+                .map_err(|e| Response::text(e.to_string()).with_status_code(400))?;
+
+            let mut name = String::new();
+            let mut password = String::new();
+            for (k, v) in pairs {
+                match k.as_str() {
+                    form_names::USER_NAME => { name = v; }
+                    form_names::PASSWORD => { password = v; }
+                    _ => {}
                 }
             }
-            Err(e) => {
-                Response::text(e.to_string()).with_status_code(503)
+
+            let Some(role) = access.check_password(&name, &password) else {
+                return Err(login_page_response(
+                    state,
+                    Some((&name, "Incorrect name or password"))
+                ).with_status_code(401))
+            };
+
+            return Err(match access.start_session(role) {
+                Ok(id) => Response::redirect_303("/")
+                    .with_additional_header(
+                        "Set-Cookie",
+                        format!(
+                            "{}={id}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}{}",
+                            access::SESSION_COOKIE,
+                            access.session_max_age().as_secs(),
+                            if request.is_secure() { "; Secure" } else { "" },
+                        )
+                    ),
+                Err(e) => Response::text(e.to_string()).with_status_code(500),
+            })
+        }
+        // Logging out is done with a POST, so that merely following a link cannot
+        // do it.
+        ("POST", page_names::LOGOUT) => {
+            let pairs = rouille::input::post::raw_urlencoded_post_input(request)
+                .map_err(|e| Response::text(e.to_string()).with_status_code(400))?;
+
+            let csrf_token = pairs.iter()
+                .find(|(k, _)| k == form_names::CSRF_TOKEN)
+                .map(|(_, v)| v.as_str());
+
+            if let Some((id, session)) = session_id
+                .and_then(|id| Some((id, access.session(id)?))) {
+                // Otherwise another site could log people out.
+                logic::Csrf::Token(&session.csrf_token)
+                    .check(csrf_token)
+                    .map_err(|e| Response::text(e.to_string()).with_status_code(403))?;
+
+                access.end_session(id);
             }
+
+            return Err(
+                Response::redirect_303(page_names::LOGIN)
+                    .with_additional_header(
+                        "Set-Cookie",
+                        format!(
+                            "{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0",
+                            access::SESSION_COOKIE,
+                        )
+                    )
+            )
         }
+        _ => {}
+    }
+
+    if let Some(authorization) = request.header("Authorization") {
+        return authorization.strip_prefix("Bearer ")
+            .and_then(|token| access.check_token(token.trim()))
+            .map(|role| Requester {
+                role,
+                // Browsers do not add this header on their own.
+                csrf_token: None,
+            })
+            .ok_or_else(|| Response::text("Invalid token").with_status_code(401))
+    }
+
+    if let Some(session) = session_id.and_then(|id| access.session(id)) {
+        return Ok(Requester {
+            role: session.role,
+            csrf_token: Some(session.csrf_token),
+        })
+    }
+
+    Err(if request.method() == "GET" {
+        Response::redirect_303(page_names::LOGIN)
+    } else {
+        Response::text("Not logged in").with_status_code(401)
     })
 }
 
+fn login_page_response(
+    state: &logic::State,
+    previous: Option<(&str, &str)>,
+) -> Response {
+    match state.login_page(previous) {
+        Ok(output) => extract_response(output),
+        Err(e) => Response::text(e.to_string()).with_status_code(500),
+    }
+}
+
 fn extract_response(output: logic::Output) -> Response {
     use logic::Output::*;
 
     match output {
         Html(html) => Response::html(html),
+        Redirect(url) => Response::redirect_303(url),
+        // The URL includes a version, so we can cache this for as long as we like.
+        Stylesheet(css) => Response::from_data("text/css; charset=utf-8", css)
+            .with_public_cache(365 * 24 * 60 * 60),
+        // Same here.
+        Script(js) => Response::from_data("text/javascript; charset=utf-8", js)
+            .with_public_cache(365 * 24 * 60 * 60),
+        Events(events) => Response {
+            status_code: 200,
+            headers: vec![
+                ("Content-Type".into(), "text/event-stream".into()),
+                ("Cache-Control".into(), "no-cache".into()),
+            ],
+            data: rouille::ResponseBody::empty(),
+            upgrade: Some(Box::new(EventStream(Some(events)))),
+        },
     }
-}
\ No newline at end of file
+}
+
+/// Writes the body of a `text/event-stream` response, which goes on until the
+/// client goes away, and writing to it fails.
+///
+/// A body that is read from is sent in chunks that are only written out once
+/// they are full, which would hold events back. So this takes over the socket
+/// once the headers have been sent, the way a protocol upgrade would, and writes
+/// each event to it as it happens. The body is then not chunked, and ends when
+/// the connection is closed. The server also sends the `Connection: upgrade`
+/// header for this, which clients ignore in anything but a 101 response.
+struct EventStream(Option<logic::Events>);
+
+impl rouille::Upgrade for EventStream {
+    fn build(&mut self, mut socket: Box<dyn rouille::ReadWrite + Send>) {
+        use std::io::Write as _;
+
+        let Some(events) = self.0.take() else {
+            return
+        };
+
+        // Sent right away, so the client knows it is connected.
+        let mut message = ": connected\n\n".to_owned();
+
+        loop {
+            let sent = socket.write_all(message.as_bytes())
+                .and_then(|()| socket.flush());
+
+            if sent.is_err() {
+                break
+            }
+
+            match events.next_message() {
+                Some(next) => message = next,
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mistyped_subcommands_are_not_taken_as_addresses() {
+        for arg in ["help", "lsit-feeds", "list-feed", "refrsh", "exprot", "add-feeds"] {
+            assert!(looks_like_subcommand(arg), "{arg}");
+        }
+
+        for arg in ["localhost", "127.0.0.1:8080", "[::1]:80", "rss.example.com", "my-server"] {
+            assert!(!looks_like_subcommand(arg), "{arg}");
+        }
+    }
+}
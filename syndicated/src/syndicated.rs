@@ -2,59 +2,132 @@ use atom_syndication::Feed;
 use rss::Channel;
 use digest::Digest;
 use meowhash::MeowHasher;
+use timestamp::Timestamp;
 
 #[derive(Clone, Debug)]
 pub struct Post {
+    /// The Atom entry ID, or RSS item GUID, if the feed provided one. Ignored by
+    /// `add_post`, which generates a new one.
+    pub id: Option<String>,
     pub title: Option<String>,
     pub summary: Option<String>,
     pub content: Option<String>,
     // TODO parsed URLs?
     pub links: Vec<String>,
+    /// Ignored by `add_post`, which uses the time the post was added.
+    pub published: Option<Timestamp>,
+    /// Ignored by `add_post`, like `published`.
+    pub updated: Option<Timestamp>,
 }
 
-pub type AddError = atom_syndication::Error;
+impl Post {
+    /// When the post was published, or failing that, last updated.
+    pub fn date(&self) -> Option<Timestamp> {
+        self.published.or(self.updated)
+    }
+}
+
+#[derive(Debug)]
+pub enum AddError {
+    /// The file is neither an Atom feed, nor an RSS one.
+    NotAFeed,
+    Atom(atom_syndication::Error),
+    Rss(rss::Error),
+    Io(std::io::Error),
+}
+
+impl core::fmt::Display for AddError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotAFeed => write!(f, "Posts can only be added to Atom or RSS feeds"),
+            Self::Atom(e) => write!(f, "{e}"),
+            Self::Rss(e) => write!(f, "{e}"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for AddError {}
 
+/// Adds `post` to the Atom or RSS feed read from `buf_read`, and writes the whole
+/// feed out to `write`. `now` is used as the time that the post was published.
 pub fn add_post(
     write: impl std::io::Write,
-    mut buf_read: impl std::io::BufRead,
+    mut buf_read: impl std::io::BufRead + std::io::Seek,
     post: Post,
+    now: Timestamp,
 ) -> Result<(), AddError> {
-    // Could support RSS here, but for our current purposes Atom is sufficent.
-
-    let mut feed = Feed::read_from(&mut buf_read)?;
-
-    use atom_syndication::{Content, Entry, FixedDateTime, Link, Text};
-
-    let mut entry = Entry::default();
-
     let mut hasher = MeowHasher::new();
 
-    let now: FixedDateTime = chrono::offset::Local::now().into();
-
     // I don't think we need to bother hashing the offset.
-    hasher.update(now.timestamp().to_le_bytes());
-    hasher.update(now.timestamp_subsec_nanos().to_le_bytes());
-    
-    entry.updated = now;
-    entry.published = Some(now);
-
-    let title = post.title.unwrap_or_else(
-        || post.links.get(0)
+    hasher.update(now.unix_nanos().to_le_bytes());
+
+    let title = post.title.clone().unwrap_or_else(
+        || post.links.first()
             .map(ToString::to_string)
-            .unwrap_or_else(|| now.to_rfc3339())
+            .unwrap_or_else(|| now.rfc3339().to_string())
     );
 
     hasher.update(&title);
-    for content in post.content.as_ref() {
+    if let Some(content) = post.content.as_ref() {
         hasher.update(content);
     }
-    for summary in post.summary.as_ref() {
+    if let Some(summary) = post.summary.as_ref() {
         hasher.update(summary);
     }
     for link in &post.links {
         hasher.update(link);
     }
 
+    let id = hasher.finalise().as_u128();
+
+    // If I need to add some different prefix to make this into a valid URN or 
+    // whatever later, then I can probably just update the feeds at that time.
+    let id = format!("mh:{id:X}");
+
+    let atom_error = match Feed::read_from(&mut buf_read) {
+        Ok(feed) => return add_atom_entry(write, feed, post, title, id, now),
+        Err(e) => e,
+    };
+
+    buf_read.seek(std::io::SeekFrom::Start(0)).map_err(AddError::Io)?;
+
+    use atom_syndication::Error as AtomError;
+    use rss::Error as RssError;
+
+    // Each parser reports the end of the input if it never finds a start tag,
+    // and a bad start tag if it finds the other kind of start tag, so we use
+    // those to tell which kind of feed a broken one was supposed to be.
+    match (Channel::read_from(&mut buf_read), atom_error) {
+        (Ok(channel), _) => add_rss_item(write, channel, post, title, id, now),
+        (Err(RssError::InvalidStartTag), AtomError::InvalidStartTag)
+        | (Err(RssError::Eof), AtomError::Eof) => Err(AddError::NotAFeed),
+        (Err(e), AtomError::InvalidStartTag) => Err(AddError::Rss(e)),
+        (Err(_), e) => Err(AddError::Atom(e)),
+    }
+}
+
+fn add_atom_entry(
+    write: impl std::io::Write,
+    mut feed: Feed,
+    post: Post,
+    title: String,
+    id: String,
+    now: Timestamp,
+) -> Result<(), AddError> {
+    use atom_syndication::{Content, Entry, FixedDateTime, Link, Text};
+
+    let mut entry = Entry::default();
+
+    let rfc3339 = now.rfc3339().to_string();
+
+    // This should only fail for years that need more than four digits.
+    let date = FixedDateTime::parse_from_rfc3339(&rfc3339)
+        .map_err(|_| AddError::Atom(atom_syndication::Error::WrongDatetime(rfc3339)))?;
+
+    entry.updated = date;
+    entry.published = Some(date);
+
     entry.title = title.into();
     entry.content = post.content.map(|value| {
         let mut content = Content::default();
@@ -63,30 +136,167 @@ pub fn add_post(
 
         content
     });
-    entry.summary = post.summary.map(|value| {
-        let mut summary = Text::default();
+    entry.summary = post.summary.map(|value| Text {
+        value,
+        ..<_>::default()
+    });
+    entry.links = post.links.into_iter().map(|href| Link {
+        href,
+        ..<_>::default()
+    }).collect();
 
-        summary.value = value;
+    entry.id = id;
 
-        summary
+    feed.entries.push(entry);
+
+    feed.write_to(write).map(|_| ()).map_err(AddError::Atom)
+}
+
+/// RSS items can only have one link, so any others are added to the end of the
+/// content.
+fn add_rss_item(
+    write: impl std::io::Write,
+    mut channel: Channel,
+    post: Post,
+    title: String,
+    id: String,
+    now: Timestamp,
+) -> Result<(), AddError> {
+    use rss::{Guid, Item};
+
+    let mut links = post.links.into_iter();
+
+    let link = links.next();
+
+    let mut content = post.content;
+
+    for link in links {
+        let link = escape_html(&link);
+
+        content.get_or_insert_with(String::new)
+            .push_str(&format!("<p><a href=\"{link}\">{link}</a></p>"));
+    }
+
+    let mut item = Item::default();
+
+    item.set_title(title);
+    item.set_description(post.summary);
+    item.set_content(content);
+    item.set_link(link);
+    item.set_guid(Guid {
+        value: id,
+        permalink: false,
     });
-    entry.links = post.links.into_iter().map(|href| {
-        let mut link = Link::default();
+    item.set_pub_date(now.rfc2822().to_string());
 
-        link.href = href;
+    channel.set_last_build_date(now.rfc2822().to_string());
 
-        link
-    }).collect();
+    channel.items.push(item);
 
-    let id = hasher.finalise().as_u128();
+    channel.write_to(write).map(|_| ()).map_err(AddError::Rss)
+}
 
-    // If I need to add some different prefix to make this into a valid URN or 
-    // whatever later, then I can probably just update the feeds at that time.
-    entry.id = format!("mh:{id:X}");
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
 
-    feed.entries.push(entry);
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Decodes the bytes of a feed into UTF-8 text. The encoding is taken from the
+/// first of these that has one: a byte order mark, the charset in `content_type`,
+/// (the value of a `Content-Type` header, if there was one,) or the XML
+/// declaration. Otherwise UTF-8 is assumed. Anything that is invalid in that
+/// encoding is replaced with U+FFFD, rather than failing the whole feed.
+///
+/// The encoding in the XML declaration is changed to UTF-8, so that the parsers
+/// used by `parse_items` do not try to decode the text a second time.
+pub fn decode(bytes: &[u8], content_type: Option<&str>) -> String {
+    use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+
+    let from_content_type = || {
+        let charset = content_type?
+            .split(';')
+            .skip(1)
+            .find_map(|parameter| {
+                let (name, value) = parameter.split_once('=')?;
+
+                name.trim()
+                    .eq_ignore_ascii_case("charset")
+                    .then(|| value.trim().trim_matches('"'))
+            })?;
+
+        Encoding::for_label(charset.as_bytes())
+    };
 
-    feed.write_to(write).map(|_| ())
+    let from_declaration = || {
+        let range = declared_encoding_range(bytes)?;
+
+        Encoding::for_label(&bytes[range])
+            // If we could read the declaration as ASCII, then the document
+            // cannot really be UTF-16.
+            .filter(|&encoding| encoding != UTF_16LE && encoding != UTF_16BE)
+    };
+
+    let encoding = Encoding::for_bom(bytes)
+        .map(|(encoding, _)| encoding)
+        .or_else(from_content_type)
+        .or_else(from_declaration)
+        .unwrap_or(UTF_8);
+
+    // This also removes any byte order mark.
+    let (text, _, _) = encoding.decode(bytes);
+    let mut text = text.into_owned();
+
+    if let Some(range) = declared_encoding_range(text.as_bytes()) {
+        text.replace_range(range, "UTF-8");
+    }
+
+    text
+}
+
+/// The position of the value of the encoding in the XML declaration at the start
+/// of `bytes`, if there is one.
+fn declared_encoding_range(bytes: &[u8]) -> Option<std::ops::Range<usize>> {
+    const START: &[u8] = b"<?xml";
+    const NAME: &[u8] = b"encoding";
+
+    let declaration = bytes.strip_prefix(START)?;
+    let end = declaration.windows(2).position(|w| w == b"?>")?;
+    let declaration = &declaration[..end];
+
+    let mut i = declaration.windows(NAME.len()).position(|w| w == NAME)?
+        + NAME.len();
+
+    let skip_whitespace = |i: &mut usize| {
+        while declaration.get(*i).is_some_and(u8::is_ascii_whitespace) {
+            *i += 1;
+        }
+    };
+
+    skip_whitespace(&mut i);
+    if declaration.get(i) != Some(&b'=') {
+        return None
+    }
+    i += 1;
+    skip_whitespace(&mut i);
+
+    let quote = *declaration.get(i).filter(|&&c| c == b'"' || c == b'\'')?;
+    let value_start = i + 1;
+    let value_end = value_start
+        + declaration[value_start..].iter().position(|&c| c == quote)?;
+
+    Some(START.len() + value_start..START.len() + value_end)
 }
 
 pub fn parse_items(
@@ -94,12 +304,24 @@ pub fn parse_items(
     output: &mut Vec<Post>,
 ) {
     if let Ok(feed) = Feed::read_from(&mut buf_read) {
+        // The dates have already been parsed by this point, so we just need them
+        // in our own type.
+        let timestamp = |date: atom_syndication::FixedDateTime| {
+            Timestamp::parse(&date.to_rfc3339()).ok()
+        };
+
         for entry in feed.entries {
             output.push(Post {
+                id: Some(entry.id),
                 title: Some(entry.title.value),
                 summary: entry.summary.map(|s| s.value),
                 content: entry.content.and_then(|c| c.value),
                 links: entry.links.into_iter().map(|l| l.href).collect(),
+                published: entry.published.and_then(timestamp),
+                // A missing updated date becomes the Unix epoch.
+                updated: Some(entry.updated)
+                    .filter(|&updated| updated.timestamp() != 0)
+                    .and_then(timestamp),
             });
         }
 
@@ -113,13 +335,447 @@ pub fn parse_items(
     if let Ok(channel) = Channel::read_from(&mut buf_read) {
         for item in channel.items {
             output.push(Post {
+                id: item.guid.map(|guid| guid.value),
                 title: item.title,
                 summary: item.description,
                 content: item.content,
                 links: item.link.into_iter().collect(),
+                published: item.pub_date
+                    .and_then(|date| Timestamp::parse(&date).ok()),
+                updated: None,
             });
         }
+    }
+}
 
-        return
+/// Makes a post out of a Markdown file, which can start with front matter like
+/// this:
+///
+/// ```text
+/// ---
+/// title: Some Title
+/// date: 2026-10-18
+/// links:
+///   - https://example.com/a
+///   - https://example.com/b
+/// ---
+/// ```
+///
+/// `summary`, `updated`, and a single `link` are also understood, and any other
+/// keys are ignored. Values can be quoted, and, as in YAML, anything after a `#`
+/// that follows whitespace is a comment. `file_name` is used as the id, and,
+/// without its extension, as the title when there isn't one.
+pub fn parse_markdown_post(file_name: &str, source: &str) -> Post {
+    let (front_matter, body) = split_front_matter(source);
+
+    let mut post = Post {
+        id: Some(file_name.to_owned()),
+        title: None,
+        summary: None,
+        content: None,
+        links: Vec::new(),
+        published: None,
+        updated: None,
+    };
+
+    // The key that any list items belong to.
+    let mut list_key = "";
+
+    for line in front_matter.lines() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue
+        }
+
+        if let Some(item) = line.strip_prefix('-') {
+            if list_key == "links" {
+                let item = front_matter_value(item);
+                if !item.is_empty() {
+                    post.links.push(item.to_owned());
+                }
+            }
+
+            continue
+        }
+
+        let Some((key, value)) = line.split_once(':') else {
+            continue
+        };
+
+        list_key = key.trim();
+
+        let value = front_matter_value(value);
+        if value.is_empty() {
+            continue
+        }
+
+        match list_key {
+            "title" => post.title = Some(value.to_owned()),
+            "summary" => post.summary = Some(value.to_owned()),
+            "date" => post.published = Timestamp::parse(value).ok(),
+            "updated" => post.updated = Timestamp::parse(value).ok(),
+            "link" | "links" => post.links.push(value.to_owned()),
+            _ => {}
+        }
+    }
+
+    if post.title.is_none() {
+        let stem = file_name.rsplit_once('.')
+            .map_or(file_name, |(stem, _)| stem);
+
+        post.title = Some(stem.to_owned());
+    }
+
+    if !body.trim().is_empty() {
+        let mut content = String::with_capacity(body.len() * 2);
+
+        pulldown_cmark::html::push_html(
+            &mut content,
+            pulldown_cmark::Parser::new_ext(
+                body,
+                pulldown_cmark::Options::ENABLE_TABLES
+                | pulldown_cmark::Options::ENABLE_STRIKETHROUGH,
+            ),
+        );
+
+        post.content = Some(content);
+    }
+
+    post
+}
+
+/// Returns the front matter, without the `---` lines around it, and the rest.
+/// If there is no front matter, the first part is empty.
+fn split_front_matter(source: &str) -> (&str, &str) {
+    let source = source.strip_prefix('\u{feff}').unwrap_or(source);
+
+    let Some(rest) = source.strip_prefix("---")
+        .and_then(|rest| rest.strip_prefix('\n').or_else(|| rest.strip_prefix("\r\n"))) else {
+        return ("", source)
+    };
+
+    let mut end = 0;
+
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            return (&rest[..end], &rest[end + line.len()..])
+        }
+
+        end += line.len();
+    }
+
+    // Without a closing line, it was not front matter after all.
+    ("", source)
+}
+
+/// The value from a line of front matter, without any quotes around it, or any
+/// comment after it.
+fn front_matter_value(value: &str) -> &str {
+    let value = value.trim();
+
+    for quote in ['"', '\''] {
+        if let Some((inner, _)) = value.strip_prefix(quote)
+            .and_then(|value| value.split_once(quote)) {
+            return inner
+        }
+    }
+
+    // Requiring whitespace before the `#` keeps the fragments of links intact.
+    let comment_start = value.char_indices()
+        .find(|&(i, c)| {
+            c == '#'
+            && (i == 0 || value[..i].ends_with(char::is_whitespace))
+        })
+        .map_or(value.len(), |(i, _)| i);
+
+    value[..comment_start].trim_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_front_matter_finds_the_lines_between_the_markers() {
+        assert_eq!(
+            split_front_matter("---\ntitle: A\n---\nBody\n"),
+            ("title: A\n", "Body\n"),
+        );
+        assert_eq!(
+            split_front_matter("\u{feff}---\r\ntitle: A\r\n---\r\nBody"),
+            ("title: A\r\n", "Body"),
+        );
+        assert_eq!(split_front_matter("---\n---\nBody"), ("", "Body"));
+    }
+
+    #[test]
+    fn split_front_matter_leaves_other_files_alone() {
+        for source in [
+            "Body\n---\ntitle: A\n---\n",
+            "--- not front matter\n---\n",
+            // No closing line.
+            "---\ntitle: A\nBody\n",
+            "",
+        ] {
+            assert_eq!(split_front_matter(source), ("", source), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn parse_markdown_post_reads_the_front_matter_from_the_readme() {
+        let post = parse_markdown_post(
+            "post.md",
+            "\
+---
+title: Some Title # The file name is used if there isn't one.
+date: 2026-10-18 # Or a full time, like 2026-10-18T14:00:00+02:00
+summary: Shown under the title
+links:
+  - https://example.com/a
+  - https://example.com/b
+---
+The post itself.
+",
+        );
+
+        assert_eq!(post.id.as_deref(), Some("post.md"));
+        assert_eq!(post.title.as_deref(), Some("Some Title"));
+        assert_eq!(post.summary.as_deref(), Some("Shown under the title"));
+        assert_eq!(post.published, Timestamp::parse("2026-10-18").ok());
+        assert!(post.published.is_some());
+        assert_eq!(post.links, ["https://example.com/a", "https://example.com/b"]);
+        assert_eq!(post.content.as_deref(), Some("<p>The post itself.</p>\n"));
+    }
+
+    #[test]
+    fn parse_markdown_post_handles_quotes_and_comments() {
+        let post = parse_markdown_post(
+            "post.md",
+            "\
+---
+# A comment on its own line.
+title: \"A # in the title\" # A comment after it.
+summary: 'Single quoted'
+link: https://example.com/page#section # Not part of the link.
+updated: 2026-10-18T14:00:00+02:00
+tags: [ignored]
+---
+",
+        );
+
+        assert_eq!(post.title.as_deref(), Some("A # in the title"));
+        assert_eq!(post.summary.as_deref(), Some("Single quoted"));
+        assert_eq!(post.links, ["https://example.com/page#section"]);
+        assert_eq!(post.updated, Timestamp::parse("2026-10-18T12:00:00Z").ok());
+        assert!(post.updated.is_some());
+        assert_eq!(post.content, None);
+    }
+
+    #[test]
+    fn parse_markdown_post_falls_back_to_the_file_name() {
+        let post = parse_markdown_post("first-post.md", "Just *Markdown*.");
+
+        assert_eq!(post.title.as_deref(), Some("first-post"));
+        assert_eq!(post.published, None);
+        assert_eq!(
+            post.content.as_deref(),
+            Some("<p>Just <em>Markdown</em>.</p>\n"),
+        );
+    }
+
+    /// `text`, which must only use the first 256 code points, in ISO-8859-1.
+    fn latin_1(text: &str) -> Vec<u8> {
+        text.chars().map(|c| u8::try_from(u32::from(c)).unwrap()).collect()
+    }
+
+    #[test]
+    fn decode_prefers_a_byte_order_mark() {
+        let mut bytes = b"\xef\xbb\xbf".to_vec();
+        bytes.extend_from_slice(
+            "<?xml version='1.0' encoding='ISO-8859-1'?><a>caf\u{e9}</a>".as_bytes()
+        );
+
+        assert_eq!(
+            decode(&bytes, Some("text/xml; charset=ISO-8859-1")),
+            "<?xml version='1.0' encoding='UTF-8'?><a>caf\u{e9}</a>",
+        );
+    }
+
+    #[test]
+    fn decode_prefers_the_content_type_to_the_declaration() {
+        let bytes = latin_1("<?xml version='1.0' encoding='UTF-8'?><a>caf\u{e9}</a>");
+
+        assert_eq!(
+            decode(&bytes, Some("application/rss+xml; charset=\"ISO-8859-1\"")),
+            "<?xml version='1.0' encoding='UTF-8'?><a>caf\u{e9}</a>",
+        );
+    }
+
+    #[test]
+    fn decode_falls_back_to_the_declaration() {
+        let bytes = latin_1("<?xml version='1.0' encoding='ISO-8859-1'?><a>caf\u{e9}</a>");
+
+        for content_type in [None, Some("application/xml")] {
+            assert_eq!(
+                decode(&bytes, content_type),
+                "<?xml version='1.0' encoding='UTF-8'?><a>caf\u{e9}</a>",
+            );
+        }
+    }
+
+    #[test]
+    fn decode_skips_unknown_labels() {
+        let bytes = latin_1("<?xml version='1.0' encoding='ISO-8859-1'?><a>caf\u{e9}</a>");
+
+        assert_eq!(
+            decode(&bytes, Some("text/xml; charset=made-up")),
+            "<?xml version='1.0' encoding='UTF-8'?><a>caf\u{e9}</a>",
+        );
+
+        // With nothing else to go on, that is UTF-8, where this is invalid.
+        let bytes = latin_1("<?xml version='1.0' encoding='made-up'?><a>caf\u{e9}</a>");
+
+        assert_eq!(
+            decode(&bytes, None),
+            "<?xml version='1.0' encoding='UTF-8'?><a>caf\u{fffd}</a>",
+        );
+    }
+
+    #[test]
+    fn decode_rewrites_only_the_declared_encoding() {
+        let bytes = latin_1(
+            "<?xml version=\"1.0\" encoding = \"windows-1252\" standalone=\"yes\"?>\
+            <a encoding='windows-1252'/>"
+        );
+
+        assert_eq!(
+            decode(&bytes, None),
+            "<?xml version=\"1.0\" encoding = \"UTF-8\" standalone=\"yes\"?>\
+            <a encoding='windows-1252'/>",
+        );
+
+        // Without a declaration, there is nothing to rewrite.
+        assert_eq!(decode(b"<a encoding='x'/>", None), "<a encoding='x'/>");
+    }
+
+    #[test]
+    fn decoded_latin_1_feeds_can_be_parsed() {
+        let rss = latin_1(
+            "<?xml version='1.0' encoding='ISO-8859-1'?>\
+            <rss version='2.0'><channel>\
+                <title>Caf\u{e9}</title><link>https://example.com</link>\
+                <description>A feed</description>\
+                <item><title>Cr\u{e8}me br\u{fb}l\u{e9}e</title></item>\
+            </channel></rss>"
+        );
+        let channel = rss::Channel::read_from(decode(&rss, None).as_bytes()).unwrap();
+        assert_eq!(channel.title(), "Caf\u{e9}");
+        assert_eq!(channel.items()[0].title(), Some("Cr\u{e8}me br\u{fb}l\u{e9}e"));
+
+        let atom = latin_1(
+            "<?xml version='1.0' encoding='ISO-8859-1'?>\
+            <feed xmlns='http://www.w3.org/2005/Atom'>\
+                <title>Caf\u{e9}</title><id>urn:feed</id>\
+                <updated>2024-01-01T00:00:00Z</updated>\
+                <entry><title>Cr\u{e8}me br\u{fb}l\u{e9}e</title><id>urn:entry</id>\
+                <updated>2024-01-01T00:00:00Z</updated></entry>\
+            </feed>"
+        );
+        let feed = atom_syndication::Feed::read_from(decode(&atom, None).as_bytes()).unwrap();
+        assert_eq!(feed.title().as_str(), "Caf\u{e9}");
+        assert_eq!(feed.entries()[0].title().as_str(), "Cr\u{e8}me br\u{fb}l\u{e9}e");
+    }
+
+    fn new_post() -> Post {
+        Post {
+            id: None,
+            title: Some("New".to_owned()),
+            summary: None,
+            content: Some("<p>Hello</p>".to_owned()),
+            links: vec![
+                "https://example.com/new".to_owned(),
+                "https://example.com/other".to_owned(),
+            ],
+            published: None,
+            updated: None,
+        }
+    }
+
+    fn add_new_post(feed: &str, now: Timestamp) -> Result<String, AddError> {
+        let mut output = Vec::new();
+        add_post(&mut output, std::io::Cursor::new(feed), new_post(), now)?;
+
+        Ok(String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn add_post_appends_an_item_to_rss_feeds() {
+        let now = Timestamp::parse("2024-03-05T06:07:08+01:00").unwrap();
+        let output = add_new_post(
+            "<?xml version='1.0'?>\
+            <rss version='2.0'><channel>\
+                <title>Feed</title><link>https://example.com</link>\
+                <description>A feed</description>\
+                <item><title>Old</title><guid>old-id</guid></item>\
+            </channel></rss>",
+            now,
+        ).unwrap();
+
+        assert!(output.contains(r#"<guid isPermaLink="false">mh:"#), "{output}");
+
+        let channel = Channel::read_from(output.as_bytes()).unwrap();
+        assert_eq!(channel.last_build_date(), Some("Tue, 05 Mar 2024 06:07:08 +0100"));
+
+        let [old, new] = channel.items() else {
+            panic!("Expected two items: {output}")
+        };
+        assert_eq!(old.title(), Some("Old"));
+        assert_eq!(old.guid().map(|guid| guid.value()), Some("old-id"));
+
+        assert_eq!(new.title(), Some("New"));
+        assert_eq!(new.link(), Some("https://example.com/new"));
+        assert_eq!(new.pub_date(), Some("Tue, 05 Mar 2024 06:07:08 +0100"));
+        assert!(!new.guid().unwrap().is_permalink());
+        // The item could only have one link, so the other is in the content.
+        assert!(new.content().unwrap().contains("https://example.com/other"));
+    }
+
+    #[test]
+    fn add_post_appends_an_entry_to_atom_feeds() {
+        let now = Timestamp::parse("2024-03-05T06:07:08+01:00").unwrap();
+        let output = add_new_post(
+            "<?xml version='1.0'?>\
+            <feed xmlns='http://www.w3.org/2005/Atom'>\
+                <title>Feed</title><id>urn:feed</id>\
+                <updated>2024-01-01T00:00:00Z</updated>\
+                <entry><title>Old</title><id>urn:old</id>\
+                <updated>2024-01-01T00:00:00Z</updated></entry>\
+            </feed>",
+            now,
+        ).unwrap();
+
+        // Not read as RSS, which would have lost the entries.
+        let feed = Feed::read_from(output.as_bytes()).unwrap();
+
+        let [old, new] = feed.entries() else {
+            panic!("Expected two entries: {output}")
+        };
+        assert_eq!(old.id(), "urn:old");
+        assert_eq!(new.title().as_str(), "New");
+        assert!(new.id().starts_with("mh:"));
+        assert_eq!(
+            new.published().map(|date| date.to_rfc3339()).as_deref(),
+            Some("2024-03-05T06:07:08+01:00"),
+        );
+        assert_eq!(new.links().len(), 2);
+    }
+
+    #[test]
+    fn add_post_only_adds_to_feeds() {
+        assert!(matches!(
+            add_new_post("<html><body>Not a feed</body></html>", Timestamp::DEFAULT),
+            Err(AddError::NotAFeed),
+        ));
     }
 }
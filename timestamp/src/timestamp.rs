@@ -1,4 +1,4 @@
-use time::{OffsetDateTime as ODT, UtcOffset as UO};
+use time::{Date, Month, OffsetDateTime as ODT, PrimitiveDateTime, Time, UtcOffset as UO};
 
 /// A local-time timestamp.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -14,25 +14,7 @@ impl Timestamp {
 
 impl core::fmt::Display for Timestamp {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-        struct W<'refr, 'f>(&'refr mut core::fmt::Formatter<'f>);
-
-        impl std::io::Write for W<'_, '_> {
-            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-                let len = buf.len();
-
-                self.0.write_str(
-                    std::str::from_utf8(buf)
-                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?
-                ).map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?;
-
-                Ok(len)
-            }
-            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
-        }
-
-        self.0.format_into(&mut W(f), &time::format_description::well_known::Rfc3339)
-            .map(|_| ())
-            .map_err(|_| core::fmt::Error)?;
+        write!(f, "{}", self.rfc3339())?;
 
         // Yes, the Z indicates UTC, but I want it to be more obvious
         if self.0.offset() == time::UtcOffset::UTC {
@@ -43,15 +25,367 @@ impl core::fmt::Display for Timestamp {
     }
 }
 
+/// See `Timestamp::rfc3339`.
+pub struct Rfc3339(Timestamp);
+
+impl core::fmt::Display for Rfc3339 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        format_into(self.0, f, &time::format_description::well_known::Rfc3339)
+    }
+}
+
+/// See `Timestamp::rfc2822`.
+pub struct Rfc2822(Timestamp);
+
+impl core::fmt::Display for Rfc2822 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        format_into(self.0, f, &time::format_description::well_known::Rfc2822)
+    }
+}
+
+fn format_into(
+    Timestamp(odt): Timestamp,
+    f: &mut core::fmt::Formatter<'_>,
+    format: &impl time::formatting::Formattable,
+) -> core::fmt::Result {
+    struct W<'refr, 'f>(&'refr mut core::fmt::Formatter<'f>);
+
+    impl std::io::Write for W<'_, '_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = buf.len();
+
+            self.0.write_str(
+                std::str::from_utf8(buf)
+                    .map_err(std::io::Error::other)?
+            ).map_err(std::io::Error::other)?;
+
+            Ok(len)
+        }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    odt.format_into(&mut W(f), format)
+        .map(|_| ())
+        .map_err(|_| core::fmt::Error)
+}
+
 impl Default for Timestamp {
     fn default() -> Self {
         Self::DEFAULT
     }
 }
 
+/// In UTC. Use `in_zone` for anywhere else.
+impl From<std::time::SystemTime> for Timestamp {
+    fn from(time: std::time::SystemTime) -> Self {
+        Self(ODT::from(time))
+    }
+}
+
 impl Timestamp {
-    pub fn now_at_offset(UtcOffset(offset): UtcOffset) -> Self {
-        Self(ODT::now_utc().to_offset(offset))
+    pub fn now_in(zone: &TimeZone) -> Self {
+        Self(ODT::now_utc()).in_zone(zone)
+    }
+
+    /// The same instant, as it would be written down in `zone`.
+    pub fn in_zone(self, zone: &TimeZone) -> Self {
+        Self(self.0.to_offset(zone.offset_at(self.0)))
+    }
+
+    /// How long ago this was, from the perspective of `now`, like "3 hours ago".
+    pub fn relative_to(self, now: Timestamp) -> Relative {
+        Relative {
+            seconds: (now.0 - self.0).whole_seconds(),
+        }
+    }
+
+    /// Just the date, like "18 Oct 2026".
+    pub fn short_date(self) -> ShortDate {
+        ShortDate(self)
+    }
+
+    /// Like "2026-10-18T14:03:07+02:00". This is what Atom feeds use.
+    pub fn rfc3339(self) -> Rfc3339 {
+        Rfc3339(self)
+    }
+
+    /// Like "Sun, 18 Oct 2026 14:03:07 +0200". This is what RSS feeds use.
+    pub fn rfc2822(self) -> Rfc2822 {
+        Rfc2822(self)
+    }
+
+    /// Nanoseconds since the Unix epoch.
+    pub fn unix_nanos(self) -> i128 {
+        self.0.unix_timestamp_nanos()
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseTimestampError();
+
+impl core::fmt::Display for ParseTimestampError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Expected a date like \"Tue, 10 Jun 2003 04:00:00 GMT\" or \
+            \"2003-06-10T04:00:00Z\""
+        )
+    }
+}
+
+impl std::error::Error for ParseTimestampError {}
+
+impl Timestamp {
+    /// Parses RFC 3339 dates, as used by Atom, and RFC 2822 dates, as used by RSS,
+    /// along with the more common ways that feeds get those wrong. Dates without
+    /// an offset are assumed to be in UTC.
+    pub fn parse(s: &str) -> Result<Self, ParseTimestampError> {
+        let s = s.trim();
+
+        parse_rfc3339(s)
+            .or_else(|| parse_rfc2822(s))
+            .map(Self)
+            .ok_or(ParseTimestampError())
+    }
+}
+
+/// Like "2003-06-10T04:00:00.5+02:00", but also with a space or nothing at all
+/// instead of the time, no seconds, or no offset.
+fn parse_rfc3339(s: &str) -> Option<ODT> {
+    let (date, rest) = s.split_at(s.find(['T', 't', ' ']).unwrap_or(s.len()));
+
+    let mut date_parts = date.split('-');
+    let year = date_parts.next()?;
+    if year.len() != 4 {
+        return None
+    }
+    let year = year.parse().ok()?;
+    let month = number(date_parts.next()?)?;
+    let day = number(date_parts.next()?)?;
+    if date_parts.next().is_some() {
+        return None
+    }
+
+    let rest = rest.get(1..).unwrap_or_default().trim_start();
+
+    let (time, offset) = match rest.find(['Z', 'z', '+', '-', ' ']) {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+
+    let time = if time.is_empty() {
+        Time::MIDNIGHT
+    } else {
+        parse_time(time)?
+    };
+
+    let offset = parse_offset(offset.trim())?;
+
+    at(year, month, day, time, offset)
+}
+
+/// Like "Tue, 10 Jun 2003 04:00:00 GMT", but also with the parts in other
+/// orders, full names, two digit years, missing parts, and so on.
+fn parse_rfc2822(s: &str) -> Option<ODT> {
+    let mut day = None;
+    let mut month = None;
+    let mut year = None;
+    let mut time = None;
+    let mut offset = None;
+
+    for token in s.split(|c: char| c.is_whitespace() || c == ',') {
+        if token.is_empty() {
+            continue
+        }
+
+        if time.is_none() && token.contains(':') {
+            // Sometimes there's no space before the offset.
+            let (t, o) = match token.find(['+', '-', 'Z']) {
+                Some(i) => token.split_at(i),
+                None => (token, ""),
+            };
+
+            time = Some(parse_time(t)?);
+
+            if !o.is_empty() {
+                offset = Some(parse_offset(o)?);
+            }
+        } else if token.bytes().all(|b| b.is_ascii_digit()) {
+            match (token.len(), day, year) {
+                (4, _, None) => year = Some(token.parse().ok()?),
+                (1 | 2, None, _) => day = Some(number(token)?),
+                // The obsolete syntax allows for these.
+                (2, Some(_), None) => year = Some(match token.parse().ok()? {
+                    y @ 0..=49 => 2000 + y,
+                    y => 1900 + y,
+                }),
+                _ => return None,
+            }
+        } else if let Some(m) = month_from_name(token).filter(|_| month.is_none()) {
+            month = Some(m);
+        } else if time.is_some() && offset.is_none() {
+            offset = Some(parse_offset(token)?);
+        }
+        // Anything else is probably the day of the week, which we can work out
+        // for ourselves.
+    }
+
+    at(
+        year?,
+        month?,
+        day?,
+        time.unwrap_or(Time::MIDNIGHT),
+        offset.unwrap_or(UO::UTC),
+    )
+}
+
+fn at(year: i32, month: u8, day: u8, time: Time, offset: UO) -> Option<ODT> {
+    let date = Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()?;
+
+    Some(PrimitiveDateTime::new(date, time).assume_offset(offset))
+}
+
+fn number(s: &str) -> Option<u8> {
+    if s.is_empty() || s.len() > 2 || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None
+    }
+
+    s.parse().ok()
+}
+
+/// Like "04:00", "04:00:00", or "04:00:00.123".
+fn parse_time(s: &str) -> Option<Time> {
+    let (s, fraction) = s.split_once('.').unwrap_or((s, ""));
+
+    let mut parts = s.split(':');
+    let hour = number(parts.next()?)?;
+    let minute = number(parts.next()?)?;
+    let second = match parts.next() {
+        // Leap seconds are not worth the trouble.
+        Some(second) => number(second)?.min(59),
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return None
+    }
+
+    let nanosecond = if fraction.is_empty() {
+        0
+    } else {
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None
+        }
+
+        let digits = &fraction[..fraction.len().min(9)];
+
+        digits.parse::<u32>().ok()? * 10u32.pow(9 - digits.len() as u32)
+    };
+
+    Time::from_hms_nano(hour, minute, second, nanosecond).ok()
+}
+
+/// Like "Z", "+02:00", "+0200", "+02", "GMT", or "EST". Unknown names, and an
+/// empty string, are taken to mean UTC.
+fn parse_offset(s: &str) -> Option<UO> {
+    let (sign, digits) = match s.as_bytes().first() {
+        Some(b'+') => (1, &s[1..]),
+        Some(b'-') => (-1, &s[1..]),
+        _ => {
+            let hours = match s.to_ascii_uppercase().as_str() {
+                "EDT" => -4,
+                "EST" | "CDT" => -5,
+                "CST" | "MDT" => -6,
+                "MST" | "PDT" => -7,
+                "PST" => -8,
+                // Including UT, GMT, and Z, which RFC 2822 says we should treat
+                // the military zones as too.
+                _ => 0,
+            };
+
+            return UO::from_hms(hours, 0, 0).ok()
+        }
+    };
+
+    let (hours, minutes) = match digits.split_once(':') {
+        Some((hours, minutes)) => (hours, minutes),
+        None if digits.len() == 4 => digits.split_at(2),
+        None => (digits, "0"),
+    };
+
+    let hours = number(hours)? as i8;
+    let minutes = number(minutes)? as i8;
+
+    UO::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+fn month_from_name(name: &str) -> Option<u8> {
+    const MONTHS: [&str; 12] = [
+        "jan", "feb", "mar", "apr", "may", "jun",
+        "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+
+    if name.len() < 3 || !name.bytes().all(|b| b.is_ascii_alphabetic()) {
+        return None
+    }
+
+    let prefix = name[..3].to_ascii_lowercase();
+
+    MONTHS.iter()
+        .position(|&month| month == prefix)
+        .map(|i| i as u8 + 1)
+}
+
+/// See `Timestamp::relative_to`.
+pub struct Relative {
+    /// Negative for times in the future.
+    seconds: i64,
+}
+
+impl core::fmt::Display for Relative {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        const MINUTE: i64 = 60;
+        const HOUR: i64 = 60 * MINUTE;
+        const DAY: i64 = 24 * HOUR;
+        // Close enough, for something this vague.
+        const MONTH: i64 = 30 * DAY;
+        const YEAR: i64 = 365 * DAY;
+
+        let seconds = self.seconds.saturating_abs();
+
+        let (count, unit) = match seconds {
+            s if s < MINUTE => return write!(f, "just now"),
+            s if s < HOUR => (s / MINUTE, "minute"),
+            s if s < DAY => (s / HOUR, "hour"),
+            s if s < MONTH => (s / DAY, "day"),
+            s if s < YEAR => (s / MONTH, "month"),
+            s => (s / YEAR, "year"),
+        };
+
+        let plural = if count == 1 { "" } else { "s" };
+
+        if self.seconds < 0 {
+            write!(f, "in {count} {unit}{plural}")
+        } else {
+            write!(f, "{count} {unit}{plural} ago")
+        }
+    }
+}
+
+/// See `Timestamp::short_date`.
+pub struct ShortDate(Timestamp);
+
+impl core::fmt::Display for ShortDate {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let date = self.0.0.date();
+        let month = date.month().to_string();
+
+        write!(
+            f,
+            "{} {} {}",
+            date.day(),
+            month.get(..3).unwrap_or(&month),
+            date.year(),
+        )
     }
 }
 
@@ -60,13 +394,224 @@ impl Timestamp {
 #[repr(transparent)]
 pub struct UtcOffset(UO);
 
+/// A place's rules for what offset from UTC to use at any given time, which can
+/// change, for example with daylight saving time.
+#[derive(Clone, Debug)]
+pub struct TimeZone(Zone);
+
+#[derive(Clone, Debug)]
+enum Zone {
+    Fixed(UO),
+    Tz(tz::TimeZone),
+}
+
+#[derive(Debug)]
+pub struct TimeZoneError(String);
+
+impl core::fmt::Display for TimeZoneError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TimeZoneError {}
+
+impl TimeZone {
+    pub const UTC: Self = Self(Zone::Fixed(UO::UTC));
+
+    pub fn fixed(UtcOffset(offset): UtcOffset) -> Self {
+        Self(Zone::Fixed(offset))
+    }
+
+    /// The zone from the `TZ` environment variable, or failing that, the one that
+    /// `/etc/localtime` is set to.
+    pub fn local() -> Result<Self, TimeZoneError> {
+        tz::TimeZone::local()
+            .map(|zone| Self(Zone::Tz(zone)))
+            .map_err(|e| TimeZoneError(
+                format!("Could not determine the local timezone: {e}")
+            ))
+    }
+
+    /// A zone from the tz database, like "Europe/Berlin".
+    pub fn named(name: &str) -> Result<Self, TimeZoneError> {
+        // The errors from this are about the name not being a valid POSIX TZ
+        // string, which it was not supposed to be in the first place.
+        tz::TimeZone::from_posix_tz(name)
+            .map(|zone| Self(Zone::Tz(zone)))
+            .map_err(|_| TimeZoneError(
+                format!("Could not find the timezone \"{name}\"")
+            ))
+    }
+
+    fn offset_at(&self, instant: ODT) -> UO {
+        match &self.0 {
+            Zone::Fixed(offset) => *offset,
+            Zone::Tz(zone) => zone.find_local_time_type(instant.unix_timestamp())
+                .ok()
+                .and_then(|time_type| UO::from_whole_seconds(time_type.ut_offset()).ok())
+                // This only happens for times far outside of the zone's rules.
+                .unwrap_or(UO::UTC),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseUtcOffsetError();
+
+impl core::fmt::Display for ParseUtcOffsetError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Expected a UTC offset like \"+09:30\", \"-05:00\", or \"Z\"")
+    }
+}
+
+impl std::error::Error for ParseUtcOffsetError {}
+
 impl UtcOffset {
-    // This will currently always return the UTC offset, (that is, offset the time by
-    // no offet at all) if there is more than one thread.
-    pub fn current_local_or_utc() -> Self {
-        Self(
-            UO::current_local_offset()
-                .unwrap_or(UO::UTC)
-        )
+    pub const UTC: Self = Self(UO::UTC);
+
+    /// Parses offsets like `+09:30`, `-05:00`, or `Z`.
+    pub fn parse(s: &str) -> Result<Self, ParseUtcOffsetError> {
+        if s == "Z" {
+            return Ok(Self::UTC)
+        }
+
+        let (sign, rest) = match s.as_bytes().first() {
+            Some(b'+') => (1, &s[1..]),
+            Some(b'-') => (-1, &s[1..]),
+            _ => return Err(ParseUtcOffsetError()),
+        };
+
+        let (hours, minutes) = rest.split_once(':')
+            .ok_or(ParseUtcOffsetError())?;
+
+        // Only digits, so that there cannot be another sign after the first.
+        let hours = number(hours).ok_or(ParseUtcOffsetError())? as i8;
+        let minutes = number(minutes).ok_or(ParseUtcOffsetError())? as i8;
+
+        UO::from_hms(sign * hours, sign * minutes, 0)
+            .map(Self)
+            .map_err(|_| ParseUtcOffsetError())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NANOS_PER_SECOND: i128 = 1_000_000_000;
+
+    #[test]
+    fn parse_reads_dates_seen_in_feeds() {
+        // The expected values are seconds since the Unix epoch.
+        for (s, expected) in [
+            // RSS, as the spec says.
+            ("Tue, 10 Jun 2003 04:00:00 GMT", 1055217600),
+            ("Tue, 10 Jun 2003 04:00:00 +0200", 1055210400),
+            ("Tue, 10 Jun 2003 04:00:00 -0530", 1055237400),
+            ("Tue, 10 Jun 2003 04:00:00 EST", 1055235600),
+            ("Tue, 10 Jun 2003 04:00:00 PDT", 1055242800),
+            ("Tue, 10 Jun 2003 04:00:00 UT", 1055217600),
+            // RSS, as it is written.
+            ("10 Jun 2003 04:00 GMT", 1055217600),
+            ("Tue, 10 Jun 2003 04:00:00 +02:00", 1055210400),
+            ("Tue, 10 Jun 2003 04:00:00+0200", 1055210400),
+            ("Tue, 10 Jun 2003 04:00:00", 1055217600),
+            ("Tue, 10 Jun 2003", 1055203200),
+            ("Tuesday, 10 June 2003 04:00:00 GMT", 1055217600),
+            ("Tue, 10 JUN 2003 04:00:00 gmt", 1055217600),
+            ("Tue Jun 10 2003 04:00:00 GMT", 1055217600),
+            ("Tue, 10 Jun 03 04:00:00 GMT", 1055217600),
+            ("Thu, 10 Jun 99 04:00:00 GMT", 928987200),
+            ("Thu, 10 Jun 49 04:00:00 GMT", 2506910400),
+            ("Sat, 10 Jun 50 04:00:00 GMT", -617313600),
+            ("Tue,  10 Jun 2003  04:00:00  GMT", 1055217600),
+            // Atom, as the spec says.
+            ("2003-06-10T04:00:00Z", 1055217600),
+            ("2003-06-10T04:00:00+02:00", 1055210400),
+            ("2003-06-10T04:00:00-05:30", 1055237400),
+            // Atom, as it is written.
+            ("2003-06-10t04:00:00z", 1055217600),
+            ("2003-06-10 04:00:00", 1055217600),
+            ("2003-06-10T04:00+02:00", 1055210400),
+            ("2003-06-10T04:00:00+0200", 1055210400),
+            ("2003-06-10", 1055203200),
+            ("  2003-06-10T04:00:00Z\n", 1055217600),
+            ("2016-12-31T23:59:60Z", 1483228799),
+        ] {
+            let parsed = Timestamp::parse(s)
+                .unwrap_or_else(|e| panic!("{s:?}: {e}"));
+
+            assert_eq!(parsed.unix_nanos(), expected * NANOS_PER_SECOND, "{s:?}");
+        }
+    }
+
+    #[test]
+    fn utc_offset_parse_reads_offsets() {
+        for (s, seconds) in [
+            ("Z", 0),
+            ("+00:00", 0),
+            ("+09:30", 9 * 3600 + 30 * 60),
+            ("-05:00", -5 * 3600),
+            ("-5:30", -(5 * 3600 + 30 * 60)),
+        ] {
+            let offset = UtcOffset::parse(s).unwrap_or_else(|e| panic!("{s:?}: {e}"));
+            assert_eq!(offset.0.whole_seconds(), seconds, "{s:?}");
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn utc_offset_parse_rejects_other_things() {
+        for s in [
+            "", "z", "UTC", "09:30", "+0930", "+09", "+-5:30", "-+5:30", "++5:30",
+            "+05:-30", "+05:+30", "+24:00", "+05:60", "+005:00", "+ 5:30",
+        ] {
+            assert!(UtcOffset::parse(s).is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn parse_keeps_fractions_of_seconds() {
+        for (s, nanos) in [
+            ("2003-06-10T04:00:00.5Z", 500_000_000),
+            ("2003-06-10T04:00:00.123456789Z", 123_456_789),
+            // Past nanoseconds is more precision than we can keep.
+            ("2003-06-10T04:00:00.1234567891Z", 123_456_789),
+        ] {
+            assert_eq!(
+                Timestamp::parse(s).unwrap().unix_nanos(),
+                1055217600 * NANOS_PER_SECOND + nanos,
+                "{s:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_rejects_what_is_not_a_date() {
+        for s in [
+            "",
+            "   ",
+            "not a date",
+            "yesterday",
+            "10/06/2003",
+            "Jun 2003",
+            "10 2003",
+            "2003-13-10T04:00:00Z",
+            "2003-02-30",
+            "2003-06-10T25:00:00Z",
+            "2003-06-10T04:60:00Z",
+            "2003-06-10T04:00:00+25:00",
+            "2003-06-10T04:00:00.5xZ",
+            "03-06-10T04:00:00Z",
+            "Tue, 31 Jun 2003 04:00:00 GMT",
+            "Tue, 10 Jun 2003 24:00:00 GMT",
+            "Tue, 10 Jun 2003 04:00:00:00 GMT",
+            "Tue, 10 Jun 20003 04:00:00 GMT",
+            "Tue, 10 Jun 2003 2004 04:00:00 GMT",
+            "Tue, 10 Jun 2003 04:00:00 +-0200",
+            "2003-06-10T04:00:00+02:-30",
+        ] {
+            assert!(Timestamp::parse(s).is_err(), "{s:?}");
+        }
+    }
+}
@@ -1,14 +1,313 @@
 use atomicwrites::{AllowOverwrite, AtomicFile};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 pub use atomicwrites::Error;
 
+/// Where to keep the previous versions of files that get overwritten, and how
+/// many of them.
+#[derive(Clone, Debug)]
+pub struct Backups {
+    /// Each file gets a directory in here, named after the file.
+    pub dir: PathBuf,
+    /// How many previous versions of each file to keep. The oldest are removed
+    /// first. Zero means no backups are made.
+    pub keep: usize,
+}
+
+impl Backups {
+    fn dir_for(&self, path: &Path) -> std::io::Result<PathBuf> {
+        path.file_name()
+            .map(|name| self.dir.join(name))
+            .ok_or_else(|| std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} has no file name to back up under", path.display())
+            ))
+    }
+}
+
+/// A previous version of a file.
+#[derive(Debug)]
+pub struct Backup {
+    id: u64,
+    path: PathBuf,
+}
+
+impl Backup {
+    /// Identifies this backup among the others of the same file.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// When this version was replaced.
+    pub fn replaced_at(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_nanos(self.id)
+    }
+}
+
+/// Replaces the file at `path` with what `f` writes, such that the file is never
+/// seen half-written, even if we crash. The new contents, and then the directory
+/// that the file is in, are synced to disk before this returns. A copy of the
+/// previous version, if there was one, is kept as described by `backups`.
 pub fn write_atomically<P, F, E>(
     path: P,
+    backups: &Backups,
     f: F
 ) -> Result<(), Error<E>>
 where
-    P: AsRef<std::path::Path>,
+    P: AsRef<Path>,
+    F: FnOnce(&mut std::fs::File) -> Result<(), E>
+{
+    let path = path.as_ref();
+
+    let backup = back_up(path, backups).map_err(Error::Internal)?;
+
+    let result = AtomicFile::new(path, AllowOverwrite).write(f);
+
+    match (&result, backup) {
+        // The new version is in place, so this failing is not worth reporting
+        // as a failed write. It will be tried again on the next one.
+        (Ok(()), Some(_)) => {
+            let _ = prune(path, backups);
+        },
+        // The previous version is still in place, so this copy of it would only
+        // push an older one out, later.
+        (Err(_), Some(backup)) => {
+            let _ = std::fs::remove_file(backup);
+        },
+        (_, None) => {},
+    }
+
+    result
+}
+
+/// Like `write_atomically`, but the file is only replaced if it still contains
+/// `expected`, which should be what was read from it before deciding what to
+/// write. A missing file counts as an empty one. This cannot stop a change that
+/// lands between the check and the replacement, but it shrinks that window from
+/// however long the caller took, down to almost nothing.
+pub fn write_atomically_if_unchanged<P, F, E>(
+    path: P,
+    expected: &[u8],
+    backups: &Backups,
+    f: F
+) -> Result<(), Error<IfUnchangedError<E>>>
+where
+    P: AsRef<Path>,
     F: FnOnce(&mut std::fs::File) -> Result<(), E>
 {
-    AtomicFile::new(path, AllowOverwrite).write(f)
-}
\ No newline at end of file
+    let path = path.as_ref();
+
+    write_atomically(path, backups, |file| {
+        f(file).map_err(IfUnchangedError::Write)?;
+
+        // The file is only moved into place after this returns, so we check as
+        // late as we can.
+        let current = match std::fs::read(path) {
+            Ok(current) => current,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(IfUnchangedError::Io(e)),
+        };
+
+        if current != expected {
+            return Err(IfUnchangedError::Changed(path.to_owned()))
+        }
+
+        Ok(())
+    })
+}
+
+#[derive(Debug)]
+pub enum IfUnchangedError<E> {
+    /// Something else changed the file since it was read, so it was left alone.
+    Changed(PathBuf),
+    Io(std::io::Error),
+    Write(E),
+}
+
+impl <E: core::fmt::Display> core::fmt::Display for IfUnchangedError<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Changed(path) => write!(
+                f,
+                "{} was changed by something else while it was being updated, so \
+                it was left alone. Try again.",
+                path.display()
+            ),
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Write(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl <E: core::fmt::Debug + core::fmt::Display> std::error::Error for IfUnchangedError<E> {}
+
+/// The backups of the file at `path`, newest first.
+pub fn backups(
+    path: impl AsRef<Path>,
+    backups: &Backups,
+) -> std::io::Result<Vec<Backup>> {
+    let dir = backups.dir_for(path.as_ref())?;
+
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut output = Vec::new();
+
+    for entry in entries {
+        let entry = entry?;
+
+        // Anything else in here was not put there by us, so we leave it alone.
+        let Some(id) = entry.file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok()) else {
+            continue
+        };
+
+        output.push(Backup { id, path: entry.path() });
+    }
+
+    output.sort_by_key(|backup| core::cmp::Reverse(backup.id));
+
+    Ok(output)
+}
+
+/// Puts the contents of `backup` back in the file at `path`. The version being
+/// replaced is backed up like any other, so this can be undone.
+pub fn restore(
+    path: impl AsRef<Path>,
+    backup: &Backup,
+    backups: &Backups,
+) -> Result<(), Error<std::io::Error>> {
+    // Read first, since making room for the new backup afterwards could remove
+    // this one.
+    let contents = std::fs::read(&backup.path).map_err(Error::Internal)?;
+
+    write_atomically(path, backups, |file| file.write_all(&contents))
+}
+
+/// Copies the file at `path`, if there is one, into its backup directory. Old
+/// backups are left for `prune`, once the new version is in place, so that a
+/// failed write does not cost us one.
+fn back_up(path: &Path, backups: &Backups) -> std::io::Result<Option<PathBuf>> {
+    if backups.keep == 0 || !path.exists() {
+        return Ok(None)
+    }
+
+    let dir = backups.dir_for(path)?;
+
+    std::fs::create_dir_all(&dir)?;
+
+    let id = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| since.as_nanos() as u64)
+        .unwrap_or_default();
+
+    // Padded so the names sort by age in a file listing.
+    let backup_path = dir.join(format!("{id:020}"));
+
+    std::fs::copy(path, &backup_path)?;
+
+    std::fs::File::open(&backup_path)?.sync_all()?;
+    sync_dir(&dir)?;
+
+    Ok(Some(backup_path))
+}
+
+/// Removes the oldest backups of the file at `path`, past the number to keep.
+fn prune(path: &Path, backups: &Backups) -> std::io::Result<()> {
+    for old in self::backups(path, backups)?.into_iter().skip(backups.keep) {
+        std::fs::remove_file(old.path)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    std::fs::File::open(dir)?.sync_all()
+}
+
+// Elsewhere, directories cannot be opened like files to sync them.
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(
+        path: &Path,
+        backups: &Backups,
+        contents: &str,
+    ) -> Result<(), Error<std::io::Error>> {
+        write_atomically(path, backups, |file| file.write_all(contents.as_bytes()))
+    }
+
+    fn backed_up(path: &Path, backups: &Backups) -> Vec<String> {
+        self::backups(path, backups).unwrap()
+            .iter()
+            .map(|backup| std::fs::read_to_string(&backup.path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_newest_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("feed.xml");
+        let backups = Backups { dir: dir.path().join("backups"), keep: 2 };
+
+        for contents in ["1", "2", "3", "4"] {
+            write(&path, &backups, contents).unwrap();
+        }
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "4");
+        assert_eq!(backed_up(&path, &backups), ["3", "2"]);
+    }
+
+    #[test]
+    fn a_failed_write_keeps_the_existing_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("feed.xml");
+        let backups = Backups { dir: dir.path().join("backups"), keep: 2 };
+
+        for contents in ["1", "2", "3"] {
+            write(&path, &backups, contents).unwrap();
+        }
+
+        let result = write_atomically(&path, &backups, |_| {
+            Err(std::io::Error::other("failed"))
+        });
+        assert!(result.is_err());
+
+        let result = write_atomically_if_unchanged(&path, b"not 3", &backups, |file| {
+            file.write_all(b"4")
+        });
+        assert!(matches!(result, Err(Error::User(IfUnchangedError::Changed(_)))));
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "3");
+        assert_eq!(backed_up(&path, &backups), ["2", "1"]);
+    }
+
+    #[test]
+    fn restoring_can_be_undone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("feed.xml");
+        let backups = Backups { dir: dir.path().join("backups"), keep: 1 };
+
+        write(&path, &backups, "1").unwrap();
+        write(&path, &backups, "2").unwrap();
+
+        let backup = &self::backups(&path, &backups).unwrap()[0];
+        restore(&path, backup, &backups).unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1");
+        assert_eq!(backed_up(&path, &backups), ["2"]);
+    }
+}